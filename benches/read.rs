@@ -0,0 +1,33 @@
+//! Compares memory-mapped and buffered reads of a large input file.
+//!
+//! Run with `cargo bench --features test-utils`.
+
+use rust_test::{read_file_accounts, test_utils::generate_input, Config};
+use std::{collections::HashMap, fs::File, time::Instant};
+
+fn main() {
+	let path = std::env::temp_dir()
+		.join(format!("rust-test-bench-read-{}.csv", std::process::id()));
+	std::fs::write(&path, generate_input(1, 1000, 5_000_000)).unwrap();
+
+	for mmap in [false, true] {
+		let conf = Config {
+			mmap,
+			..Default::default()
+		};
+		let start = Instant::now();
+		read_file_accounts(
+			&mut HashMap::new(),
+			&File::open(&path).unwrap(),
+			&conf,
+		)
+		.unwrap();
+		println!(
+			"{}: {:?}",
+			if mmap { "mmap" } else { "buffered" },
+			start.elapsed()
+		);
+	}
+
+	std::fs::remove_file(&path).unwrap();
+}
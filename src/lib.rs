@@ -0,0 +1,8363 @@
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils;
+
+use flate2::{write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use std::{
+	borrow::Cow,
+	cell::RefCell,
+	collections::{hash_map::Entry, BTreeMap, HashMap, HashSet, VecDeque},
+	fmt,
+	fs::File,
+	io::{self, BufRead, Read, Seek, Write},
+	num::{NonZeroU32, NonZeroUsize},
+	ops::RangeInclusive,
+	path::Path,
+	str::FromStr,
+	sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Processing options
+#[derive(Default)]
+pub struct Config {
+	/// Input has no header row and relies on the fixed
+	/// `type, client, tx, amount` column order
+	pub no_header: bool,
+
+	/// Abort processing on the first rejected transaction instead of
+	/// recording it in the [Report]
+	pub strict: bool,
+
+	/// Validate the entire input against a copy of the accounts before
+	/// applying any of it, so a rejected transaction leaves the accounts
+	/// unchanged. Implies [Config::strict].
+	///
+	/// Applies to [read_accounts_with] and the functions built on it.
+	/// Their `on_transaction` callbacks are called during validation.
+	pub all_or_nothing: bool,
+
+	/// Bound the deposit registry by dropping deposits with transaction IDs
+	/// more than this many IDs behind the highest one seen so far.
+	/// Disputes of such deposits are rejected.
+	///
+	/// Relies on transaction IDs increasing monotonically. Deposits under an
+	/// ongoing dispute are never dropped, so the dispute can still be
+	/// resolved or charged back.
+	///
+	/// `None` keeps every deposit.
+	pub dispute_window: Option<u32>,
+
+	/// Bound the deposit registry by evicting the least recently used
+	/// deposits of all accounts beyond this many. Disputes of evicted
+	/// deposits are rejected.
+	///
+	/// Deposits under an ongoing dispute are never evicted. Only the IDs of
+	/// as many most recently evicted deposits are kept. Disputes of deposits
+	/// evicted before those are handled like ones of unknown deposits.
+	///
+	/// `None` keeps every deposit.
+	pub deposit_cache: Option<NonZeroUsize>,
+
+	/// Format to write the account statuses in
+	pub output_format: OutputFormat,
+
+	/// Base columns of the account status output to write in order.
+	/// `None` writes all of [OutColumn::ALL].
+	pub columns: Option<Vec<OutColumn>>,
+
+	/// Zero-pad client IDs of the account status output to this many digits,
+	/// like `00042` for 5. Padded IDs are written as strings.
+	/// `None` writes them unpadded.
+	pub client_width: Option<usize>,
+
+	/// Representation of the `locked` column of the account status output
+	pub bool_style: BoolStyle,
+
+	/// Transactions blocked by the locked and frozen account flags
+	pub flag_policy: FlagPolicy,
+
+	/// Skip rows that fail to parse and record them in
+	/// [Report::parse_errors] instead of aborting on the first one
+	pub collect_errors: bool,
+
+	/// Handling of the disputed amount left over after a chargeback of only
+	/// part of it
+	pub partial_chargeback: PartialChargeback,
+
+	/// Retention of charged back deposits in the deposit registry
+	pub charged_back: ChargedBackDeposits,
+
+	/// Charge back funds without locking the account
+	pub no_lock_on_chargeback: bool,
+
+	/// Write extended columns with additional account details
+	pub extended_output: bool,
+
+	/// Write `available_minor`, `held_minor` and `total_minor` columns with
+	/// the exact signed balances in minor currency units alongside the
+	/// formatted ones
+	pub audit_columns: bool,
+
+	/// Currency label to write in a `currency` column of each account.
+	/// `None` omits the column.
+	pub currency: Option<String>,
+
+	/// Reject deposits and withdrawals with a transaction ID not greater than
+	/// that of the previous deposit or withdrawal of the same client.
+	/// For upstreams that guarantee monotonic transaction IDs per client,
+	/// where a violation indicates corruption.
+	pub verify_tx_order: bool,
+
+	/// Reject resolves of deposits not under dispute with
+	/// [RejectReason::ResolveWithoutDispute] instead of ignoring them, as they
+	/// usually indicate upstream confusion about the dispute state.
+	/// Always enabled by [Config::strict].
+	pub reject_undisputed_resolves: bool,
+
+	/// Reject deposits and withdrawals without a positive amount with
+	/// [RejectReason::NonPositiveAmount] and disputes, resolves and
+	/// chargebacks with any amount with [RejectReason::UnexpectedAmount].
+	/// Disables partial resolves and chargebacks.
+	pub validate_signs: bool,
+
+	/// Reject withdrawals blocked by the account flags with
+	/// [RejectReason::LockedAccount] and ones of more than the available
+	/// funds with [RejectReason::InsufficientFunds] instead of ignoring them
+	pub report_refused_withdrawals: bool,
+
+	/// Handling of rows with an unknown transaction type
+	pub unknown_tx: UnknownTxPolicy,
+
+	/// Reject deposits and withdrawals of more than this many major currency
+	/// units. `None` does not limit amounts.
+	pub max_amount: Option<f64>,
+
+	/// Reject deposits of less than this many major currency units.
+	/// `None` does not limit deposits.
+	pub min_deposit: Option<f64>,
+
+	/// Reject withdrawals that would leave less than this many major currency
+	/// units, but more than zero, available. Withdrawing the entire available
+	/// funds is allowed. `None` allows any residual balance.
+	pub min_residual: Option<f64>,
+
+	/// Available funds in major currency units of every new account, such as
+	/// balances migrated from another system. The opening balance is not
+	/// a transaction and can not be disputed. `None` opens accounts empty.
+	pub opening_balance: Option<f64>,
+
+	/// Record amounts of input rows, which differ from their value rounded to
+	/// minor units by more than this many major currency units, in
+	/// [Report::precision_warnings]. `None` rounds silently.
+	pub precision_warning: Option<f64>,
+
+	/// Reject disputes that would raise the held funds of an account above
+	/// this many major currency units. `None` does not limit held funds.
+	pub max_held: Option<f64>,
+
+	/// Append a `checksum,<sum of totals>,<number of accounts>` row to CSV
+	/// output, so consumers can verify they received all of it
+	pub checksum: bool,
+
+	/// Begin CSV output of [write_accounts] and [process_progressive] with
+	/// a `# scale=<decimals> units_per_major=<units>` comment line recording
+	/// the decimal places of the amounts and [Config::units_per_major], so
+	/// the output is self-describing
+	pub scale_header: bool,
+
+	/// Append a `TOTAL` row with the sums of the amount columns of all
+	/// accounts to CSV output, before any checksum row. The client column is
+	/// not numeric, so it can not be mistaken for a client.
+	pub grand_total: bool,
+
+	/// Omit unlocked accounts with no funds from the output.
+	/// Binary state snapshots always include every account.
+	pub suppress_empty: bool,
+
+	/// Omit accounts without any [Account::applied] transactions, such as of
+	/// clients only present in rejected or ignored rows, from the output.
+	/// Binary state snapshots always include every account.
+	pub suppress_inactive: bool,
+
+	/// End text output without a line terminator after the final row instead
+	/// of exactly one
+	pub no_trailing_newline: bool,
+
+	/// Input of [process_progressive] is grouped by client. Each account is
+	/// then finalized as soon as a row of another client follows, instead of
+	/// by `finalize` rows. Rows of a client after its group abort processing
+	/// with [ProcessError::Ungrouped].
+	pub sorted_by_client: bool,
+
+	/// Compress the account status output of [write_accounts] with gzip after
+	/// formatting it. Not supported by [process_progressive] and [repl], which
+	/// write rows as they are processed.
+	pub gzip_output: bool,
+
+	/// Interpretation of held funds during disputes
+	pub accounting: Accounting,
+
+	/// Handling of disputes of more than the available funds
+	pub dispute_shortfall: DisputeShortfall,
+
+	/// Handling of transactions that would overflow a balance
+	pub on_overflow: OverflowPolicy,
+
+	/// Handling of transactions of client 0
+	pub client_zero: ClientZero,
+
+	/// Apply administrative [Transaction::Adjustment] corrections. Rejected
+	/// with [RejectReason::AdminNotAllowed] otherwise.
+	pub allow_admin: bool,
+
+	/// Handling of held funds of restored state snapshots that disagree with
+	/// the disputes open on the account
+	pub state_check: StateCheck,
+
+	/// Reject transactions with IDs in this range, such as `0..=0` for feeds
+	/// that use 0 as a sentinel of malformed rows
+	pub reserved_tx: Option<RangeInclusive<u32>>,
+
+	/// Record withdrawals in the deposit registry with a negative amount, so
+	/// they can be disputed, resolved and charged back like deposits.
+	/// Disputed withdrawals hold no funds, as those have already left the
+	/// account. A charged back withdrawal is returned to the available funds.
+	/// Withdrawals reusing the ID of a recorded transaction are rejected with
+	/// [RejectReason::DuplicateTx].
+	pub dispute_withdrawals: bool,
+
+	/// Keep the IDs of withdrawals not recorded by
+	/// [Config::dispute_withdrawals] to reject disputes of them with
+	/// [RejectReason::DisputeOnWithdrawalUnsupported] instead of ignoring
+	/// them like disputes of unknown transactions
+	pub detect_withdrawal_disputes: bool,
+
+	/// Open a dispute on an undisputed transaction, when a chargeback of it
+	/// arrives, for feeds that skip the explicit dispute step. Otherwise such
+	/// chargebacks are ignored. The dispute stays open, if the chargeback
+	/// itself is then rejected.
+	pub chargeback_opens_dispute: bool,
+
+	/// Write amounts as integer minor currency units instead of major units
+	/// of 4 decimal precision, for exact re-ingestion
+	pub minor_units: bool,
+
+	/// Minor currency units in each major unit. `None` uses 10_000.
+	///
+	/// Input amounts are rounded to the nearest minor unit. Output in major
+	/// units has 4 decimal precision, so it is only exact, if 10_000 is
+	/// divisible by this, like for a 1/8 subdivision. Otherwise use
+	/// [Config::minor_units] for exact output. State snapshots store minor
+	/// units and must be restored with the same setting.
+	pub units_per_major: Option<NonZeroU32>,
+
+	/// Decimal places of amounts in major units, like 2 for cents. Overrides
+	/// [Config::units_per_major] with `10^scale` minor units per major unit
+	/// and writes major unit output with this precision instead of 4
+	/// decimals. Scales above 9 are treated as 9.
+	///
+	/// Can be detected from the input with [detect_scale]. `None` uses
+	/// [Config::units_per_major] and 4 decimal output.
+	pub scale: Option<u32>,
+
+	/// Decimal separator of output amounts in major units and, with
+	/// [Config::amount_prefix], input amounts. `None` uses `.`.
+	pub decimal_separator: Option<char>,
+
+	/// Field delimiter of CSV output. `None` uses `,`.
+	pub output_delimiter: Option<u8>,
+
+	/// Quote character of CSV input fields containing the delimiter.
+	/// `None` uses `"`.
+	pub quote: Option<u8>,
+
+	/// Resolve disputes still open after this many subsequent input rows,
+	/// releasing the held funds back to available ones. `None` keeps
+	/// disputes open until explicitly resolved or charged back.
+	pub auto_resolve: Option<u32>,
+
+	/// Hold disputes of unknown deposits for this many subsequent input rows
+	/// and apply them, once the deposit arrives within them, to tolerate
+	/// minor ordering glitches. Disputes whose deposit does not arrive in time
+	/// or before the end of input are rejected with
+	/// [RejectReason::DisputeGraceExpired]. `None` ignores such disputes
+	/// immediately.
+	pub dispute_grace: Option<u32>,
+
+	/// Write accounts in order of creation by [Account::first_seen] instead
+	/// of an arbitrary one. Map outputs like [OutputFormat::JsonMap]
+	#[cfg_attr(feature = "yaml", doc = "and [`OutputFormat::Yaml`]")]
+	/// are always sorted by client ID.
+	pub sort_first_seen: bool,
+
+	/// Memory-map input files in [read_file_accounts] instead of reading them
+	/// through a buffer. Avoids copying multi-GB inputs.
+	pub mmap: bool,
+
+	/// Fail with [ProcessError::Truncated], if the input does not end with a
+	/// line terminator, which indicates the final row was cut off.
+	/// Processing is aborted after the final row has already been applied,
+	/// so the accounts should be discarded.
+	pub detect_truncation: bool,
+
+	/// Treat input lines longer than this many bytes, excluding the line
+	/// terminator, as unparsable rows without buffering them in full.
+	/// Only applies to streams and files. [Ingest] buffers rows until they are
+	/// terminated.
+	///
+	/// `None` allows lines of any length.
+	pub max_line_bytes: Option<usize>,
+
+	/// Maximum number of threads of [read_files_parallel].
+	/// `None` processes every file on its own thread.
+	///
+	/// Processing of a single input is sequential, so this is the only bound
+	/// on concurrency. There is no separate per-client limit.
+	pub max_threads: Option<NonZeroUsize>,
+
+	/// Number of data rows to skip before processing any. Disputes of
+	/// skipped deposits are handled like of any other unknown transaction.
+	pub skip_rows: u64,
+
+	/// Maximum number of data rows to process after [Config::skip_rows].
+	/// `None` processes the rest of the input.
+	pub take_rows: Option<u64>,
+
+	/// Clients to process transactions of. Transactions of other clients are
+	/// skipped without creating their accounts.
+	pub clients: ClientFilter,
+
+	/// Record transactions skipped due to [Config::clients] in
+	/// [Report::skipped]
+	pub report_skipped: bool,
+
+	/// Parser of input amount fields. `None` uses [DecimalAmountParser].
+	pub amount_parser: Option<Box<dyn AmountParser>>,
+
+	/// Currency symbol to strip from the start of input amounts, like `$` in
+	/// `$5.0000`. Amounts without it are parsed as is.
+	/// When set, input amounts also use [Config::decimal_separator], so
+	/// `€5,0000` can be parsed with `€` and `,`.
+	pub amount_prefix: Option<String>,
+}
+
+/// Parser of input amount fields into major currency units
+pub trait AmountParser: Send + Sync {
+	/// Parse the trimmed, non-empty amount field `s` or describe why it could
+	/// not be parsed
+	fn parse(&self, s: &str) -> Result<f64, String>;
+}
+
+/// Default [AmountParser] of plain decimal numbers like `1.5000`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DecimalAmountParser;
+
+impl AmountParser for DecimalAmountParser {
+	fn parse(&self, s: &str) -> Result<f64, String> {
+		s.parse()
+			.map_err(|e| format!("invalid amount {}: {}", s, e))
+	}
+}
+
+/// Set of clients to process transactions of
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum ClientFilter {
+	/// All clients
+	#[default]
+	All,
+
+	/// Only the listed clients
+	Allow(HashSet<u16>),
+
+	/// All clients except the listed ones
+	Deny(HashSet<u16>),
+}
+
+impl ClientFilter {
+	/// Transactions of `client` are processed
+	pub fn includes(&self, client: u16) -> bool {
+		match self {
+			Self::All => true,
+			Self::Allow(set) => set.contains(&client),
+			Self::Deny(set) => !set.contains(&client),
+		}
+	}
+}
+
+/// Handling of disputes of more than the available funds, such as of deposits
+/// already withdrawn
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DisputeShortfall {
+	/// Hold the entire disputed amount, pushing available funds below zero
+	/// to claw back the withdrawn part
+	#[default]
+	AllowNegative,
+
+	/// Only hold what is available, keeping available funds at zero, and
+	/// record the rest in [Account::shortfall]. Has no effect with
+	/// [Accounting::Annotate], which does not take disputed funds from the
+	/// available ones.
+	Clamp,
+
+	/// Reject the dispute with
+	/// [RejectReason::InsufficientAvailableForDispute], treating the funds as
+	/// already spent. Has no effect with [Accounting::Annotate] either.
+	Reject,
+}
+
+impl FromStr for DisputeShortfall {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Ok(match s {
+			"allow-negative" => Self::AllowNegative,
+			"clamp" => Self::Clamp,
+			"reject" => Self::Reject,
+			_ => return Err(format!("unknown dispute shortfall mode: {}", s)),
+		})
+	}
+}
+
+impl Config {
+	/// Rejected transactions abort processing
+	fn is_strict(&self) -> bool {
+		self.strict || self.all_or_nothing
+	}
+}
+
+/// Handling of transactions of client 0, which some feeds use for system
+/// transactions like interest and fees
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ClientZero {
+	/// Treat client 0 like any other client
+	#[default]
+	Ordinary,
+
+	/// Apply the transactions to a system account, which is omitted from the
+	/// account status output. Binary state snapshots still include it.
+	System,
+
+	/// Reject the transactions with [RejectReason::ClientZero]
+	Reject,
+}
+
+impl FromStr for ClientZero {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Ok(match s {
+			"ordinary" => Self::Ordinary,
+			"system" => Self::System,
+			"reject" => Self::Reject,
+			_ => return Err(format!("unknown client zero mode: {}", s)),
+		})
+	}
+}
+
+/// Representation of the `locked` column in the account status output
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BoolStyle {
+	/// `true` and `false`
+	#[default]
+	TrueFalse,
+
+	/// `1` and `0`
+	OneZero,
+
+	/// `yes` and `no`
+	YesNo,
+}
+
+impl FromStr for BoolStyle {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Ok(match s {
+			"true/false" => Self::TrueFalse,
+			"1/0" => Self::OneZero,
+			"yes/no" => Self::YesNo,
+			_ => return Err(format!("unknown bool style: {}", s)),
+		})
+	}
+}
+
+/// Handling of transactions that would overflow a balance
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+	/// Reject the transaction, leaving the account as is
+	#[default]
+	Abort,
+
+	/// Apply the transaction with the overflowing balances clamped to the
+	/// range of minor unit amounts and record it in [Report::clamped]
+	ClampAndReport,
+}
+
+impl FromStr for OverflowPolicy {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Ok(match s {
+			"abort" => Self::Abort,
+			"clamp-and-report" => Self::ClampAndReport,
+			_ => return Err(format!("unknown overflow mode: {}", s)),
+		})
+	}
+}
+
+/// Handling of inconsistent accounts restored by [load_state_with].
+///
+/// Held funds must equal the disputed amounts of the account's open disputes
+/// less their [Deposit::shortfall]. The disputed, charged back and released
+/// amounts of each open dispute must add up to the amount of its deposit.
+/// Total funds are derived from available and held ones, so they follow any
+/// correction.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum StateCheck {
+	/// Restore accounts as is
+	#[default]
+	Ignore,
+
+	/// Recompute the disputed amounts of the open disputes and held funds
+	/// from them
+	Correct,
+
+	/// Fail with [ProcessError::InconsistentState]
+	Fail,
+}
+
+impl FromStr for StateCheck {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Ok(match s {
+			"ignore" => Self::Ignore,
+			"correct" => Self::Correct,
+			"fail" => Self::Fail,
+			_ => return Err(format!("unknown state check mode: {}", s)),
+		})
+	}
+}
+
+/// Interpretation of held funds during disputes
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Accounting {
+	/// Disputed funds move from available to held.
+	///
+	/// - dispute: available -= disputed, held += disputed
+	/// - resolve: available += disputed, held -= disputed
+	/// - chargeback: held -= charged back
+	///
+	/// Total is available + held.
+	#[default]
+	MoveToHeld,
+
+	/// Held only annotates the disputed funds, which stay available until
+	/// charged back.
+	///
+	/// - dispute: held += disputed
+	/// - resolve: held -= disputed
+	/// - chargeback: available -= charged back, held -= charged back
+	///
+	/// Total is available.
+	Annotate,
+}
+
+impl FromStr for Accounting {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Ok(match s {
+			"move-to-held" => Self::MoveToHeld,
+			"annotate" => Self::Annotate,
+			_ => return Err(format!("unknown accounting mode: {}", s)),
+		})
+	}
+}
+
+/// Handling of rows with an unknown transaction type
+#[derive(Default)]
+pub enum UnknownTxPolicy {
+	/// Treat the row as unparsable, aborting processing or recording it in
+	/// [Report::parse_errors] with [Config::collect_errors]
+	#[default]
+	Fail,
+
+	/// Skip the row
+	Ignore,
+
+	/// Reject the transaction
+	Reject,
+
+	/// Pass the row and the client's account to a custom handler, which can
+	/// apply the transaction or reject it
+	Handler(UnknownTxHandler),
+}
+
+/// Custom handler of rows with an unknown transaction type
+pub type UnknownTxHandler = Box<
+	dyn Fn(&UnknownTx, &mut Account) -> Result<(), RejectReason> + Send + Sync,
+>;
+
+impl FromStr for UnknownTxPolicy {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Ok(match s {
+			"fail" => Self::Fail,
+			"ignore" => Self::Ignore,
+			"reject" => Self::Reject,
+			_ => return Err(format!("unknown transaction type policy: {}", s)),
+		})
+	}
+}
+
+/// Row with an unknown transaction type passed to an [UnknownTxHandler]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UnknownTx<'a> {
+	/// Line of the row in the input
+	pub line: u64,
+
+	/// Transaction type as written in the input
+	pub typ: &'a str,
+
+	/// Client ID
+	pub client: u16,
+
+	/// Transaction ID
+	pub tx: u32,
+
+	/// Transaction amount in major currency units
+	pub amount: Option<f64>,
+}
+
+/// Handling of the disputed amount left over after a partial chargeback.
+///
+/// A chargeback row may specify an amount less than the disputed amount to
+/// only charge back that much. Any chargeback locks the account, unless
+/// [Config::no_lock_on_chargeback] is set.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum PartialChargeback {
+	/// Leftover stays held under the ongoing dispute, which can then be
+	/// resolved or charged back further
+	#[default]
+	StayHeld,
+
+	/// Leftover is released back to available funds and the dispute ends
+	Resolve,
+}
+
+impl FromStr for PartialChargeback {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Ok(match s {
+			"stay-held" => Self::StayHeld,
+			"resolve" => Self::Resolve,
+			_ => return Err(format!("unknown partial chargeback mode: {}", s)),
+		})
+	}
+}
+
+/// Retention of deposits in the registry after their dispute ended with
+/// a chargeback
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ChargedBackDeposits {
+	/// Keep them for audit and to reject resolves with
+	/// [RejectReason::ResolveAfterChargeback]
+	#[default]
+	Retain,
+
+	/// Remove them to bound memory. Later transactions referencing them are
+	/// then ignored like those of unknown deposits.
+	Prune,
+}
+
+impl FromStr for ChargedBackDeposits {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Ok(match s {
+			"retain" => Self::Retain,
+			"prune" => Self::Prune,
+			_ => return Err(format!("unknown charged back mode: {}", s)),
+		})
+	}
+}
+
+/// Transactions blocked on accounts with the [Account::locked] and
+/// [Account::frozen] flags set. An account with both flags set is blocked
+/// from the union of both.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlagPolicy {
+	/// Blocked on locked accounts.
+	/// Defaults to only withdrawals.
+	pub locked: Blocked,
+
+	/// Blocked on frozen accounts.
+	/// Defaults to deposits and withdrawals.
+	pub frozen: Blocked,
+}
+
+impl Default for FlagPolicy {
+	fn default() -> Self {
+		Self {
+			locked: Blocked {
+				withdrawals: true,
+				..Default::default()
+			},
+			frozen: Blocked {
+				deposits: true,
+				withdrawals: true,
+				..Default::default()
+			},
+		}
+	}
+}
+
+/// Set of transaction types blocked by an account flag
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Blocked {
+	pub deposits: bool,
+	pub withdrawals: bool,
+
+	/// Disputes, resolves and chargebacks
+	pub disputes: bool,
+}
+
+impl Blocked {
+	fn union(self, other: Self) -> Self {
+		Self {
+			deposits: self.deposits || other.deposits,
+			withdrawals: self.withdrawals || other.withdrawals,
+			disputes: self.disputes || other.disputes,
+		}
+	}
+}
+
+/// Supported account status output formats
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+	/// CSV with a header row
+	#[default]
+	Csv,
+
+	/// JSON array of account objects
+	Json,
+
+	/// JSON object mapping client IDs to account objects sorted by client ID.
+	/// Convenient for direct lookup of a client downstream.
+	JsonMap,
+
+	/// YAML mapping of client IDs to accounts with the same fields as
+	/// [OutputFormat::JsonMap]
+	#[cfg(feature = "yaml")]
+	Yaml,
+
+	/// Compact binary snapshot of the full account state, including the
+	/// deposit registries. Can be loaded with [load_state] as the starting
+	/// state of a later pipeline stage.
+	Bincode,
+}
+
+impl FromStr for OutputFormat {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Ok(match s {
+			"csv" => Self::Csv,
+			"json" => Self::Json,
+			"json-map" => Self::JsonMap,
+			#[cfg(feature = "yaml")]
+			"yaml" => Self::Yaml,
+			"bincode" => Self::Bincode,
+			_ => return Err(format!("unknown output format: {}", s)),
+		})
+	}
+}
+
+/// Base column of the account status output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutColumn {
+	Client,
+	Available,
+	Held,
+	Total,
+	Locked,
+}
+
+impl OutColumn {
+	/// All base columns in the default order
+	pub const ALL: [Self; 5] = [
+		Self::Client,
+		Self::Available,
+		Self::Held,
+		Self::Total,
+		Self::Locked,
+	];
+
+	/// Name of the column as used in the output
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Self::Client => "client",
+			Self::Available => "available",
+			Self::Held => "held",
+			Self::Total => "total",
+			Self::Locked => "locked",
+		}
+	}
+}
+
+impl FromStr for OutColumn {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		Self::ALL
+			.into_iter()
+			.find(|c| c.as_str() == s)
+			.ok_or_else(|| format!("unknown output column: {}", s))
+	}
+}
+
+/// Error that aborted processing
+#[derive(Debug)]
+pub enum ProcessError {
+	/// Reading the input or writing the output failed
+	Io(io::Error),
+
+	/// Input is not valid CSV or a row could not be parsed
+	Csv(csv::Error),
+
+	/// Writing JSON output failed
+	Json(serde_json::Error),
+
+	/// Writing a binary state snapshot failed
+	StateEncode(bincode::error::EncodeError),
+
+	/// Reading a binary state snapshot failed
+	StateDecode(bincode::error::DecodeError),
+
+	/// Row could not be parsed
+	Parse(ParseError),
+
+	/// Transaction rejected in strict mode
+	InvalidRow(Rejection),
+
+	/// Reading a zip archive failed
+	Zip(zip::result::ZipError),
+
+	/// No zip archive entry was named and the archive does not contain
+	/// exactly one file
+	ZipEntryRequired,
+
+	/// Input does not end with a line terminator, so its final row on the
+	/// given line was likely cut off
+	Truncated(u64),
+
+	/// Held funds or open disputes of the restored account of the client
+	/// disagree with each other or the amounts of the disputed deposits
+	InconsistentState(u16),
+
+	/// Row of a client after the group of its rows with
+	/// [Config::sorted_by_client]
+	Ungrouped { line: u64, client: u16 },
+}
+
+impl fmt::Display for ProcessError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Io(e) => e.fmt(f),
+			Self::Csv(e) => e.fmt(f),
+			Self::Json(e) => e.fmt(f),
+			Self::StateEncode(e) => e.fmt(f),
+			Self::StateDecode(e) => e.fmt(f),
+			Self::Parse(e) => e.fmt(f),
+			Self::InvalidRow(rej) => rej.fmt(f),
+			Self::Zip(e) => e.fmt(f),
+			Self::ZipEntryRequired => f.write_str(
+				"zip archive does not contain exactly one file: \
+				entry name required",
+			),
+			Self::Truncated(line) => write!(
+				f,
+				"line {}: input truncated: final row not terminated",
+				line
+			),
+			Self::InconsistentState(client) => write!(
+				f,
+				"held funds of client {} disagree with open disputes",
+				client
+			),
+			Self::Ungrouped { line, client } => write!(
+				f,
+				"line {}: input not grouped by client: client {} already \
+				finalized",
+				line, client
+			),
+		}
+	}
+}
+
+impl std::error::Error for ProcessError {}
+
+impl From<io::Error> for ProcessError {
+	fn from(e: io::Error) -> Self {
+		Self::Io(e)
+	}
+}
+
+impl From<csv::Error> for ProcessError {
+	fn from(e: csv::Error) -> Self {
+		Self::Csv(e)
+	}
+}
+
+impl From<serde_json::Error> for ProcessError {
+	fn from(e: serde_json::Error) -> Self {
+		Self::Json(e)
+	}
+}
+
+impl From<bincode::error::EncodeError> for ProcessError {
+	fn from(e: bincode::error::EncodeError) -> Self {
+		Self::StateEncode(e)
+	}
+}
+
+impl From<bincode::error::DecodeError> for ProcessError {
+	fn from(e: bincode::error::DecodeError) -> Self {
+		Self::StateDecode(e)
+	}
+}
+
+impl From<zip::result::ZipError> for ProcessError {
+	fn from(e: zip::result::ZipError) -> Self {
+		Self::Zip(e)
+	}
+}
+
+/// Reason a transaction was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+	/// Resolve of a deposit that has already been charged back
+	ResolveAfterChargeback,
+
+	/// Resolve of a deposit not under dispute with
+	/// [Config::reject_undisputed_resolves]
+	ResolveWithoutDispute,
+
+	/// Dispute of a transaction that is older than [Config::dispute_window]
+	DisputeWindowExpired,
+
+	/// Chargeback amount is zero or negative or any amount is not a finite
+	/// number within the range of minor unit amounts
+	InvalidAmount,
+
+	/// Partial chargeback of more than the disputed amount
+	AmountExceedsDisputed,
+
+	/// Deposit or withdrawal without an amount
+	MissingAmount,
+
+	/// Transaction ID not greater than the client's previous one with
+	/// [Config::verify_tx_order]
+	OutOfOrder,
+
+	/// Unknown transaction type with [UnknownTxPolicy::Reject]
+	UnknownType,
+
+	/// Deposit or withdrawal amount above [Config::max_amount]
+	AmountTooLarge,
+
+	/// Transaction ID in [Config::reserved_tx]
+	ReservedTxId,
+
+	/// Client excluded by [Config::clients]
+	ClientExcluded,
+
+	/// Transaction would overflow a balance.
+	///
+	/// With [OverflowPolicy::ClampAndReport] [Account::apply_with] returns it
+	/// after applying the transaction with the overflowing balances clamped.
+	Overflow,
+
+	/// Dispute would hold more than [Config::max_held]
+	HoldLimitExceeded,
+
+	/// Deposit or withdrawal reusing the ID of a transaction of the other
+	/// type in strict mode
+	TxTypeConflict,
+
+	/// Transaction of a client, whose account was already finalized and
+	/// written by [process_progressive]
+	AccountFinalized,
+
+	/// Dispute of a transaction already under dispute
+	DuplicateDispute,
+
+	/// Dispute of a deposit evicted from the registry by
+	/// [Config::deposit_cache]
+	DepositEvicted,
+
+	/// Dispute of more than the available funds with
+	/// [DisputeShortfall::Reject]
+	InsufficientAvailableForDispute,
+
+	/// Transaction of client 0 with [ClientZero::Reject]
+	ClientZero,
+
+	/// Adjustment without [Config::allow_admin]
+	AdminNotAllowed,
+
+	/// Adjustment or, with [Config::report_refused_withdrawals], withdrawal
+	/// that would overdraw the available funds
+	InsufficientFunds,
+
+	/// Withdrawal from an account with withdrawals blocked by its flags with
+	/// [Config::report_refused_withdrawals]
+	LockedAccount,
+
+	/// Withdrawal that would leave less than [Config::min_residual] available
+	DustBalance,
+
+	/// Dispute of a withdrawal with [Config::detect_withdrawal_disputes]
+	DisputeOnWithdrawalUnsupported,
+
+	/// Deposit or withdrawal of a zero or negative amount with
+	/// [Config::validate_signs]
+	NonPositiveAmount,
+
+	/// Dispute, resolve or chargeback with an amount with
+	/// [Config::validate_signs]
+	UnexpectedAmount,
+
+	/// Deposit amount below [Config::min_deposit]
+	BelowMinDeposit,
+
+	/// Dispute held by [Config::dispute_grace], whose deposit did not arrive
+	/// in time
+	DisputeGraceExpired,
+
+	/// Withdrawal with [Config::dispute_withdrawals] reusing the ID of a
+	/// transaction already in the deposit registry
+	DuplicateTx,
+
+	/// Resolve of a deposit, whose disputed, charged back and released amounts
+	/// do not add up to its amount, as can be restored with
+	/// [StateCheck::Ignore]
+	InconsistentDeposit,
+}
+
+impl RejectReason {
+	/// Machine-readable name of the reason
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Self::ResolveAfterChargeback => "resolve_after_chargeback",
+			Self::ResolveWithoutDispute => "resolve_without_dispute",
+			Self::DisputeWindowExpired => "dispute_window_expired",
+			Self::InvalidAmount => "invalid_amount",
+			Self::AmountExceedsDisputed => "amount_exceeds_disputed",
+			Self::MissingAmount => "missing_amount",
+			Self::OutOfOrder => "out_of_order",
+			Self::UnknownType => "unknown_type",
+			Self::AmountTooLarge => "amount_too_large",
+			Self::ReservedTxId => "reserved_tx_id",
+			Self::ClientExcluded => "client_excluded",
+			Self::Overflow => "overflow",
+			Self::HoldLimitExceeded => "hold_limit_exceeded",
+			Self::TxTypeConflict => "tx_type_conflict",
+			Self::AccountFinalized => "account_finalized",
+			Self::DuplicateDispute => "duplicate_dispute",
+			Self::DepositEvicted => "deposit_evicted",
+			Self::InsufficientAvailableForDispute => {
+				"insufficient_available_for_dispute"
+			}
+			Self::ClientZero => "client_zero",
+			Self::AdminNotAllowed => "admin_not_allowed",
+			Self::InsufficientFunds => "insufficient_funds",
+			Self::LockedAccount => "locked_account",
+			Self::DustBalance => "dust_balance",
+			Self::DisputeOnWithdrawalUnsupported => {
+				"dispute_on_withdrawal_unsupported"
+			}
+			Self::NonPositiveAmount => "non_positive_amount",
+			Self::UnexpectedAmount => "unexpected_amount",
+			Self::BelowMinDeposit => "below_min_deposit",
+			Self::DisputeGraceExpired => "dispute_grace_expired",
+			Self::DuplicateTx => "duplicate_tx",
+			Self::InconsistentDeposit => "inconsistent_deposit",
+		}
+	}
+}
+
+impl fmt::Display for RejectReason {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		f.write_str(self.as_str())
+	}
+}
+
+/// Transaction successfully applied to its account, passed to the
+/// `on_transaction` hook of [read_accounts_with].
+///
+/// Only transactions counted by [Account::applied] are passed. Ones without
+/// an effect, like withdrawals blocked by the account flags or disputes of
+/// unknown deposits, are not.
+#[derive(Debug)]
+pub struct AppliedTx<'a> {
+	/// Line of the transaction row in the input or, for resolves applied by
+	/// [Config::auto_resolve], of the row after which they were applied
+	pub line: u64,
+
+	/// Client ID
+	pub client: u16,
+
+	/// Applied transaction
+	pub transaction: Transaction<'a>,
+
+	/// Change of the available funds in minor currency units
+	pub available_delta: i64,
+
+	/// Change of the held funds in minor currency units
+	pub held_delta: i64,
+
+	/// Account state after applying the transaction
+	pub account: &'a Account,
+}
+
+/// Owned record of the effect of a transaction applied to its account,
+/// produced by [process_with_events] for event-sourced consumers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxEvent {
+	/// Line of the transaction row in the input
+	pub line: u64,
+
+	/// Transaction type
+	pub typ: TxType,
+
+	/// Client ID
+	pub client: u16,
+
+	/// Transaction ID
+	pub tx: u32,
+
+	/// Change of the available funds in minor currency units
+	pub available_delta: i64,
+
+	/// Change of the held funds in minor currency units
+	pub held_delta: i64,
+
+	/// Available funds after applying the transaction in minor currency units
+	pub available: i64,
+
+	/// Held funds after applying the transaction in minor currency units
+	pub held: i64,
+
+	/// Account locked after applying the transaction
+	pub locked: bool,
+}
+
+impl From<&AppliedTx<'_>> for TxEvent {
+	fn from(applied: &AppliedTx<'_>) -> Self {
+		Self {
+			line: applied.line,
+			typ: applied.transaction.typ(),
+			client: applied.client,
+			tx: applied.transaction.tx(),
+			available_delta: applied.available_delta,
+			held_delta: applied.held_delta,
+			available: applied.account.available,
+			held: applied.account.held,
+			locked: applied.account.locked,
+		}
+	}
+}
+
+/// Transaction that was not applied to its account
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejection {
+	/// Line of the transaction row in the input
+	pub line: u64,
+
+	/// Transaction type
+	pub typ: TxType,
+
+	/// Client ID
+	pub client: u16,
+
+	/// Transaction ID
+	pub tx: u32,
+
+	/// Why the transaction was rejected
+	pub reason: RejectReason,
+}
+
+impl fmt::Display for Rejection {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"line {}: {} of tx {} for client {} rejected: {}",
+			self.line,
+			self.typ.as_str(),
+			self.tx,
+			self.client,
+			self.reason
+		)
+	}
+}
+
+/// Balance adjustment applied with [Config::allow_admin]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Adjustment {
+	/// Line of the transaction row in the input
+	pub line: u64,
+
+	/// Client ID
+	pub client: u16,
+
+	/// Transaction ID
+	pub tx: u32,
+
+	/// Signed amount in major currency units as read from the input
+	pub amount: f64,
+
+	/// Reason given for the adjustment
+	pub reason: Option<String>,
+}
+
+impl fmt::Display for Adjustment {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"line {}: adjustment of tx {} for client {} by {}",
+			self.line, self.tx, self.client, self.amount
+		)?;
+		if let Some(reason) = &self.reason {
+			write!(f, ": {}", reason)?;
+		}
+		Ok(())
+	}
+}
+
+/// Input row that could not be parsed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+	/// Line of the row in the input
+	pub line: u64,
+
+	/// Description of the parsing failure
+	pub message: String,
+}
+
+impl ParseError {
+	fn new(line: u64, e: &csv::Error) -> Self {
+		Self {
+			line,
+			message: match e.kind() {
+				// Position is already included in the line
+				csv::ErrorKind::Deserialize { err, .. } => err.to_string(),
+				csv::ErrorKind::UnequalLengths {
+					expected_len, len, ..
+				} => format!("expected {} fields, found {}", expected_len, len),
+				_ => e.to_string(),
+			},
+		}
+	}
+}
+
+impl fmt::Display for ParseError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "line {}: {}", self.line, self.message)
+	}
+}
+
+/// Input amount with more precision than minor units can represent
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PrecisionWarning {
+	/// Line of the transaction row in the input
+	pub line: u64,
+
+	/// Transaction type
+	pub typ: TxType,
+
+	/// Client ID
+	pub client: u16,
+
+	/// Transaction ID
+	pub tx: u32,
+
+	/// Amount in major currency units as read from the input
+	pub amount: f64,
+
+	/// Amount in major currency units after rounding to minor units
+	pub rounded: f64,
+}
+
+impl fmt::Display for PrecisionWarning {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(
+			f,
+			"line {}: {} of tx {} for client {}: amount {} rounded to {}",
+			self.line,
+			self.typ.as_str(),
+			self.tx,
+			self.client,
+			self.amount,
+			self.rounded
+		)
+	}
+}
+
+/// Report of transactions rejected during processing
+#[derive(Debug, Default)]
+pub struct Report {
+	/// Rejected transactions in input order
+	pub rejections: Vec<Rejection>,
+
+	/// Rows skipped due to parsing failures in input order.
+	/// Only populated with [Config::collect_errors].
+	pub parse_errors: Vec<ParseError>,
+
+	/// Transactions of clients excluded by [Config::clients] in input order.
+	/// Only populated with [Config::report_skipped].
+	pub skipped: Vec<Rejection>,
+
+	/// Transaction counts by type
+	pub tx_stats: BTreeMap<TxType, TxStats>,
+
+	/// Amounts rounded by more than [Config::precision_warning] in input
+	/// order
+	pub precision_warnings: Vec<PrecisionWarning>,
+
+	/// Transactions applied with clamped balances in input order.
+	/// Only populated with [OverflowPolicy::ClampAndReport].
+	pub clamped: Vec<Rejection>,
+
+	/// Applied balance adjustments in input order
+	pub adjustments: Vec<Adjustment>,
+}
+
+/// Counts of transactions of one type
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TxStats {
+	/// Successfully parsed rows
+	pub read: u64,
+
+	/// Transactions applied to their account, as counted by
+	/// [Account::applied]
+	pub applied: u64,
+
+	/// Rejected transactions
+	pub rejected: u64,
+
+	/// Transactions neither applied nor rejected, as they had no effect, like
+	/// withdrawals blocked by the account flags or disputes of unknown
+	/// deposits
+	pub ignored: u64,
+}
+
+impl Report {
+	/// Record a rejected transaction or abort processing with it in strict
+	/// mode
+	fn reject(
+		&mut self,
+		conf: &Config,
+		rej: Rejection,
+	) -> Result<(), ProcessError> {
+		if conf.is_strict() {
+			return Err(ProcessError::InvalidRow(rej));
+		}
+		self.rejections.push(rej);
+		Ok(())
+	}
+
+	/// Append the records of a report for a subsequent input
+	pub fn extend(&mut self, other: Report) {
+		self.rejections.extend(other.rejections);
+		self.parse_errors.extend(other.parse_errors);
+		self.skipped.extend(other.skipped);
+		self.precision_warnings.extend(other.precision_warnings);
+		self.clamped.extend(other.clamped);
+		self.adjustments.extend(other.adjustments);
+		for (typ, s) in other.tx_stats {
+			let stats = self.tx_stats.entry(typ).or_default();
+			stats.read += s.read;
+			stats.applied += s.applied;
+			stats.rejected += s.rejected;
+			stats.ignored += s.ignored;
+		}
+	}
+}
+
+/// Process a CSV stream `r` and write the account status CSV to `w`
+pub fn process(
+	w: &mut impl Write,
+	r: &mut impl Read,
+	conf: &Config,
+) -> Result<Report, ProcessError> {
+	let mut accounts = HashMap::<u16, Account>::with_capacity(64);
+	let report = read_accounts(&mut accounts, r, conf)?;
+	write_accounts(w, accounts, conf)?;
+	Ok(report)
+}
+
+/// Process a CSV stream `r` and write the account statuses to `sink`
+pub fn process_to(
+	sink: impl OutputSink,
+	r: &mut impl Read,
+	conf: &Config,
+) -> Result<Report, ProcessError> {
+	let mut accounts = HashMap::<u16, Account>::with_capacity(64);
+	let report = read_accounts(&mut accounts, r, conf)?;
+	write_accounts_to(sink, &accounts, conf)?;
+	Ok(report)
+}
+
+/// Process CSV streams `readers` in order as one logical input and write the
+/// account status CSV to `w`.
+///
+/// Account state carries across readers. Each reader is a separate CSV
+/// document with its own header row, unless [Config::no_header] is set.
+pub fn process_readers(
+	w: &mut impl Write,
+	readers: Vec<Box<dyn Read>>,
+	conf: &Config,
+) -> Result<Report, ProcessError> {
+	let mut accounts = HashMap::<u16, Account>::with_capacity(64);
+	let mut report = Report::default();
+	for mut r in readers {
+		report.extend(read_accounts(&mut accounts, &mut r, conf)?);
+	}
+	write_accounts(w, accounts, conf)?;
+	Ok(report)
+}
+
+/// Interactively apply transactions read from `r` one header-less
+/// `type, client, tx, amount` row per line and write the status of the
+/// affected account to `w` after each one.
+///
+/// Rows that can not be parsed or are rejected are reported to `w` without
+/// ending the session. All account statuses are written at the end of input.
+pub fn repl(
+	r: &mut impl BufRead,
+	w: &mut impl Write,
+	conf: &Config,
+) -> Result<(), ProcessError> {
+	let mut accounts = HashMap::<u16, Account>::new();
+	let mut buf = String::new();
+	let mut line = 0;
+	writeln!(
+		w,
+		"{}",
+		["client", "available", "held", "total", "locked"].join(
+			&char::from(conf.output_delimiter.unwrap_or(b',')).to_string()
+		)
+	)?;
+	w.flush()?;
+	loop {
+		buf.clear();
+		if r.read_line(&mut buf)? == 0 {
+			break;
+		}
+		line += 1;
+		if buf.trim().is_empty() {
+			continue;
+		}
+
+		let mut fields =
+			buf.trim().split(',').map(str::trim).collect::<Vec<_>>();
+
+		// Omitted trailing amount and reason fields are parsed as empty
+		if fields.len() < 5 {
+			fields.resize(5, "");
+		}
+		let rec = csv::StringRecord::from(fields);
+		let row: InRow = match rec.deserialize(None) {
+			Ok(row) => row,
+			Err(e) => {
+				writeln!(w, "{}", ParseError::new(line, &e))?;
+				w.flush()?;
+				continue;
+			}
+		};
+
+		let amount = match row.amount(conf) {
+			Ok(amount) => amount,
+			Err(message) => {
+				writeln!(w, "{}", ParseError { line, message })?;
+				w.flush()?;
+				continue;
+			}
+		};
+
+		let acc = account_entry(&mut accounts, row.client, conf);
+		let res = row
+			.transaction(line, rec.get(0).unwrap_or_default(), amount)
+			.and_then(|tx| acc.apply_with(&tx, conf));
+		if let Err(reason) = res {
+			writeln!(
+				w,
+				"{}",
+				Rejection {
+					line,
+					typ: row.typ,
+					client: row.client,
+					tx: row.tx,
+					reason,
+				}
+			)?;
+		}
+		let mut out = csv::WriterBuilder::new()
+			.has_headers(false)
+			.delimiter(conf.output_delimiter.unwrap_or(b','))
+			.from_writer(&mut *w);
+		out.serialize(OutRow::new(row.client, acc, conf))?;
+		out.flush()?;
+	}
+
+	write_accounts(w, accounts, conf)
+}
+
+/// Apply transactions from CSV file `file` to `accounts`.
+///
+/// With [Config::mmap] regular files are memory-mapped. Anything else, like
+/// pipes, is read through a buffer.
+pub fn read_file_accounts(
+	accounts: &mut HashMap<u16, Account>,
+	file: &File,
+	conf: &Config,
+) -> Result<Report, ProcessError> {
+	if conf.mmap && file.metadata()?.is_file() {
+		// SAFETY: the input file must not be modified for the duration of
+		// processing. This is the same requirement any reader of the file
+		// has for consistent results.
+		let map = unsafe { memmap2::Mmap::map(file)? };
+		read_accounts(accounts, &mut &map[..], conf)
+	} else {
+		read_accounts(accounts, &mut io::BufReader::new(file), conf)
+	}
+}
+
+/// Apply transactions from every `.csv` file in directory `dir` to `accounts`
+/// in sorted file name order. Other files are skipped.
+pub fn read_dir_accounts(
+	accounts: &mut HashMap<u16, Account>,
+	dir: &Path,
+	conf: &Config,
+) -> Result<Report, ProcessError> {
+	let mut paths = std::fs::read_dir(dir)?
+		.map(|e| e.map(|e| e.path()))
+		.collect::<Result<Vec<_>, _>>()?;
+	paths.retain(|p| p.is_file() && p.extension().is_some_and(|e| e == "csv"));
+	paths.sort_unstable();
+
+	let mut report = Report::default();
+	for path in paths {
+		report.extend(read_file_accounts(accounts, &File::open(path)?, conf)?);
+	}
+	Ok(report)
+}
+
+/// Process CSV files `paths` concurrently into separate account maps, which
+/// are then combined in order with the same handling of clients present in
+/// several files as [merge_accounts]. Each file is processed on one of up to
+/// [Config::max_threads] threads.
+///
+/// Transactions depend on the preceding ones of the same client, so this only
+/// matches processing the files in order, if each client's transactions are
+/// all in one file. Reports are combined in file order and the error of the
+/// first failed file in order is returned.
+pub fn read_files_parallel(
+	paths: &[impl AsRef<Path> + Sync],
+	conf: &Config,
+) -> Result<(HashMap<u16, Account>, Report), ProcessError> {
+	let threads = conf
+		.max_threads
+		.map_or(paths.len(), |n| n.get().min(paths.len()));
+	let next = AtomicUsize::new(0);
+	let mut results = std::thread::scope(|s| {
+		let handles = (0..threads)
+			.map(|_| {
+				s.spawn(|| {
+					let mut done = Vec::new();
+					loop {
+						let i = next.fetch_add(1, Ordering::Relaxed);
+						let Some(p) = paths.get(i) else {
+							break;
+						};
+						let mut accounts = HashMap::new();
+						let res =
+							File::open(p).map_err(ProcessError::from).and_then(
+								|f| read_file_accounts(&mut accounts, &f, conf),
+							);
+						done.push((i, res.map(|r| (accounts, r))));
+					}
+					done
+				})
+			})
+			.collect::<Vec<_>>();
+		handles
+			.into_iter()
+			.flat_map(|h| h.join().expect("file processing thread panicked"))
+			.collect::<Vec<_>>()
+	});
+	results.sort_unstable_by_key(|(i, _)| *i);
+
+	let mut accounts = HashMap::<u16, Account>::new();
+	let mut report = Report::default();
+	for (_, res) in results {
+		let (file_accounts, file_report) = res?;
+
+		// Clients first seen in this file follow all of the previous files'
+		// ones, like with sequential processing
+		let offset = accounts.len() as u64;
+		for (cl, mut acc) in file_accounts {
+			match accounts.entry(cl) {
+				Entry::Occupied(mut e) => e.get_mut().merge(acc),
+				Entry::Vacant(e) => {
+					acc.first_seen += offset;
+					e.insert(acc);
+				}
+			}
+		}
+		report.extend(file_report);
+	}
+	Ok((accounts, report))
+}
+
+/// Apply transactions from the CSV file `entry` of a zip archive to
+/// `accounts`.
+///
+/// If no `entry` is named, the archive must contain exactly one file, which is
+/// then processed.
+pub fn read_zip_accounts(
+	accounts: &mut HashMap<u16, Account>,
+	archive: impl Read + Seek,
+	entry: Option<&str>,
+	conf: &Config,
+) -> Result<Report, ProcessError> {
+	let mut archive = zip::ZipArchive::new(archive)?;
+	let name = match entry {
+		Some(name) => name.to_owned(),
+		None => {
+			let mut files = archive
+				.file_names()
+				.collect::<Result<Vec<_>, _>>()?
+				.into_iter()
+				.filter(|n| !n.ends_with('/'));
+			match (files.next(), files.next()) {
+				(Some(name), None) => name.into_owned(),
+				_ => return Err(ProcessError::ZipEntryRequired),
+			}
+		}
+	};
+	let report = read_accounts(accounts, &mut archive.by_name(&name)?, conf)?;
+	Ok(report)
+}
+
+/// Load account state from a snapshot written with [OutputFormat::Bincode]
+pub fn load_state(
+	r: &mut impl Read,
+) -> Result<HashMap<u16, Account>, ProcessError> {
+	load_state_with(r, &Config::default())
+}
+
+/// Load account state from a snapshot written with [OutputFormat::Bincode]
+/// and check it according to [Config::state_check]
+pub fn load_state_with(
+	r: &mut impl Read,
+	conf: &Config,
+) -> Result<HashMap<u16, Account>, ProcessError> {
+	let mut accounts: HashMap<u16, Account> =
+		bincode::serde::decode_from_std_read(r, bincode::config::standard())?;
+	if conf.state_check != StateCheck::Ignore {
+		for (&client, acc) in &mut accounts {
+			for d in acc.deposits.values_mut() {
+				if d.dispute_state != DisputeState::Initiated
+					|| d.is_consistent()
+				{
+					continue;
+				}
+				match d
+					.amount
+					.checked_sub(d.charged_back)
+					.and_then(|a| a.checked_sub(d.released))
+				{
+					Some(disputed)
+						if conf.state_check == StateCheck::Correct =>
+					{
+						d.disputed = disputed;
+					}
+					_ => return Err(ProcessError::InconsistentState(client)),
+				}
+			}
+
+			let held = acc
+				.deposits
+				.values()
+				.filter(|d| d.dispute_state == DisputeState::Initiated)
+				.map(|d| d.held_share(d.disputed))
+				.sum();
+			if acc.held != held {
+				if conf.state_check == StateCheck::Fail {
+					return Err(ProcessError::InconsistentState(client));
+				}
+				acc.held = held;
+			}
+		}
+	}
+	Ok(accounts)
+}
+
+/// Apply transactions from a CSV stream `r` to `accounts`
+pub fn read_accounts(
+	accounts: &mut HashMap<u16, Account>,
+	r: &mut impl Read,
+	conf: &Config,
+) -> Result<Report, ProcessError> {
+	read_accounts_with(accounts, r, conf, |_| ())
+}
+
+/// Apply transactions from a CSV stream `r` to `accounts` and call
+/// `on_transaction` after each successfully applied one
+pub fn read_accounts_with(
+	accounts: &mut HashMap<u16, Account>,
+	r: &mut impl Read,
+	conf: &Config,
+	on_transaction: impl FnMut(&AppliedTx),
+) -> Result<Report, ProcessError> {
+	read_accounts_hooked(accounts, r, conf, on_transaction, |_, _, _| ())
+}
+
+/// Apply transactions from a CSV stream `r` to `accounts` and describe each
+/// input row and its outcome to `trace` for debugging.
+///
+/// Each row is echoed with its trimmed fields like `line 4: dispute,1,1,` and
+/// followed by the [format_trace] of each transaction it applied, its
+/// [Rejection] or [ParseError] or `line 4: not applied`.
+pub fn read_accounts_traced(
+	accounts: &mut HashMap<u16, Account>,
+	r: &mut impl Read,
+	conf: &Config,
+	mut trace: impl FnMut(&str),
+) -> Result<Report, ProcessError> {
+	// Collected by the transaction hook and written after the row
+	let applied = RefCell::new(Vec::new());
+	let mut reported = (0, 0, 0);
+	read_accounts_hooked(
+		accounts,
+		r,
+		conf,
+		|tx| applied.borrow_mut().push(format_trace(tx, conf)),
+		|line, rec, report| {
+			trace(&format!(
+				"line {}: {}",
+				line,
+				rec.iter().collect::<Vec<_>>().join(",")
+			));
+
+			let mut outcomes = applied.take();
+			let (rejections, skipped, parse_errors) = reported;
+			outcomes.extend(
+				report.rejections[rejections..]
+					.iter()
+					.chain(&report.skipped[skipped..])
+					.map(ToString::to_string),
+			);
+			outcomes.extend(
+				report.parse_errors[parse_errors..]
+					.iter()
+					.map(ToString::to_string),
+			);
+			reported = (
+				report.rejections.len(),
+				report.skipped.len(),
+				report.parse_errors.len(),
+			);
+			if outcomes.is_empty() {
+				outcomes.push(format!("line {}: not applied", line));
+			}
+			for o in outcomes {
+				trace(&o);
+			}
+		},
+	)
+}
+
+/// Apply transactions from a CSV stream `r` to `accounts`, calling
+/// `on_transaction` after each successfully applied one and `on_record` with
+/// the report so far after each input record
+fn read_accounts_hooked(
+	accounts: &mut HashMap<u16, Account>,
+	r: &mut impl Read,
+	conf: &Config,
+	on_transaction: impl FnMut(&AppliedTx),
+	on_record: impl FnMut(u64, &csv::StringRecord, &Report),
+) -> Result<Report, ProcessError> {
+	if !conf.all_or_nothing {
+		return read_stream(accounts, r, conf, on_transaction, on_record);
+	}
+
+	// Validated by applying the input to a copy, which then replaces the
+	// original
+	let mut staged = accounts.clone();
+	let report = read_stream(&mut staged, r, conf, on_transaction, on_record)?;
+	*accounts = staged;
+	Ok(report)
+}
+
+/// Apply transactions from a CSV stream `r` to `accounts` as they are read
+fn read_stream(
+	accounts: &mut HashMap<u16, Account>,
+	r: &mut impl Read,
+	conf: &Config,
+	mut on_transaction: impl FnMut(&AppliedTx),
+	mut on_record: impl FnMut(u64, &csv::StringRecord, &Report),
+) -> Result<Report, ProcessError> {
+	let mut r = input_reader(LastByte::new(r), conf);
+	let headers = if conf.no_header {
+		None
+	} else {
+		Some(r.headers()?.clone())
+	};
+	let mut rows = RowProcessor::new(conf, headers);
+
+	// Read input CSV rows
+	let mut rec = csv::StringRecord::new();
+	let mut last_line = None;
+	while rows.read_record(&mut r, &mut rec, 0)? {
+		let line = rec.position().map_or(0, |p| p.line());
+		last_line = Some(line);
+		rows.process(accounts, &rec, line, &mut on_transaction)?;
+		on_record(line, &rec, &rows.report);
+	}
+	rows.finish()?;
+
+	// An interrupted transfer cuts off the final row, which the CSV reader
+	// accepts as is
+	if conf.detect_truncation
+		&& !rows.taken
+		&& r.get_ref().get_ref().last != Some(b'\n')
+	{
+		if let Some(line) = last_line {
+			return Err(ProcessError::Truncated(line));
+		}
+	}
+
+	Ok(rows.report)
+}
+
+/// Apply transactions from a CSV stream `r` to accounts kept internally and
+/// yield the effect of each successfully applied one as it is read.
+///
+/// Rejected transactions and ones without an effect, like for
+/// [read_accounts_with], are skipped. Errors, like in strict mode, end the
+/// stream. [Config::detect_truncation] is not supported.
+pub fn process_with_events<'c, R: Read + 'c>(
+	r: R,
+	conf: &'c Config,
+) -> impl Iterator<Item = Result<TxEvent, ProcessError>> + 'c {
+	let mut r = input_reader(r, conf);
+	let mut rows = None;
+	let mut accounts = HashMap::new();
+	let mut rec = csv::StringRecord::new();
+	let mut events = VecDeque::new();
+	let mut done = false;
+	std::iter::from_fn(move || loop {
+		if let Some(e) = events.pop_front() {
+			return Some(Ok(e));
+		}
+		if done {
+			return None;
+		}
+
+		let rows = match &mut rows {
+			Some(rows) => rows,
+			None => {
+				let headers = if conf.no_header {
+					None
+				} else {
+					match r.headers() {
+						Ok(h) => Some(h.clone()),
+						Err(e) => {
+							done = true;
+							return Some(Err(e.into()));
+						}
+					}
+				};
+				rows.insert(RowProcessor::new(conf, headers))
+			}
+		};
+		let res = rows.read_record(&mut r, &mut rec, 0).and_then(|read| {
+			if read {
+				let line = rec.position().map_or(0, |p| p.line());
+				rows.process(&mut accounts, &rec, line, &mut |applied| {
+					events.push_back(TxEvent::from(applied))
+				})?;
+			} else {
+				done = true;
+				rows.finish()?;
+			}
+			Ok(())
+		});
+		if let Err(e) = res {
+			done = true;
+			return Some(Err(e));
+		}
+	})
+}
+
+/// Parse the rows of a CSV stream `r` without applying them to any accounts
+pub fn read_rows<R: Read>(
+	r: R,
+) -> impl Iterator<Item = Result<InRow, ProcessError>> {
+	read_rows_with(r, &Config::default())
+}
+
+/// Parse the rows of a CSV stream `r` without applying them to any accounts.
+///
+/// Only the input format options of `conf` are used.
+pub fn read_rows_with<R: Read>(
+	r: R,
+	conf: &Config,
+) -> impl Iterator<Item = Result<InRow, ProcessError>> {
+	let max_line = conf.max_line_bytes;
+	let mut rows = input_reader(r, conf).into_deserialize();
+	let mut next = None;
+	std::iter::from_fn(move || {
+		if next.is_none() {
+			next = rows.next();
+		}
+
+		// Oversized lines preceding the row
+		let before = match next {
+			Some(_) => rows.reader().position().line(),
+			None => u64::MAX,
+		};
+		if let Some(line) = rows.reader_mut().get_mut().oversized(before) {
+			return Some(Err(ProcessError::Parse(ParseError {
+				line,
+				message: line_too_long(max_line),
+			})));
+		}
+		next.take().map(|row| row.map_err(ProcessError::from))
+	})
+}
+
+/// Detect [Config::scale] from the longest fractional part of the amounts in
+/// the first `rows` rows of CSV stream `r`.
+///
+/// Only the input format options of `conf` are used. Returns `None` to fall
+/// back to the default, if the rows have no amounts or any row or amount is
+/// not a plain decimal number of at most 4 decimal places.
+pub fn detect_scale<R: Read>(r: R, rows: usize, conf: &Config) -> Option<u32> {
+	let sep = match &conf.amount_prefix {
+		Some(_) => conf.decimal_separator.unwrap_or('.'),
+		None => '.',
+	};
+	let mut scale = None;
+	for row in read_rows_with(r, conf).take(rows) {
+		let row = row.ok()?;
+		let s = match row.amount.as_deref().map(str::trim) {
+			Some(s) if !s.is_empty() => s,
+			_ => continue,
+		};
+		let s = conf
+			.amount_prefix
+			.as_deref()
+			.and_then(|p| s.strip_prefix(p))
+			.unwrap_or(s);
+		let (int, frac) = s.split_once(sep).unwrap_or((s, ""));
+		let digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+		if !digits(int.trim_start_matches(['-', '+']))
+			|| !digits(frac)
+			|| frac.len() > 4
+		{
+			return None;
+		}
+		scale = scale.max(Some(frac.len() as u32));
+	}
+	scale
+}
+
+/// CSV reader of input stream `r`
+fn input_reader<R: Read>(r: R, conf: &Config) -> csv::Reader<LineLimit<R>> {
+	csv::ReaderBuilder::new()
+		.trim(csv::Trim::All)
+		.quote(conf.quote.unwrap_or(b'"'))
+		// Without headers serde maps the record to `InRow` fields by position
+		.has_headers(!conf.no_header)
+		.from_reader(LineLimit::new(r, conf.max_line_bytes))
+}
+
+/// Parse error message of lines longer than [Config::max_line_bytes]
+fn line_too_long(max: Option<usize>) -> String {
+	format!("line exceeds {} bytes", max.unwrap_or_default())
+}
+
+/// Process a CSV stream `r` and write the account status CSV to `w`
+/// progressively, instead of keeping all accounts in memory until the end of
+/// input.
+///
+/// A `finalize, <client>, <any tx ID>,` row marks a client's account as
+/// final. Its status is then written and the account freed. Any later
+/// transactions of the client, such as late disputes, are rejected with
+/// [RejectReason::AccountFinalized]. Accounts not finalized are written at
+/// the end of input.
+///
+/// Always writes CSV without a checksum row.
+pub fn process_progressive(
+	w: &mut impl Write,
+	r: &mut impl Read,
+	conf: &Config,
+) -> Result<Report, ProcessError> {
+	let mut r = input_reader(r, conf);
+	let headers = if conf.no_header {
+		None
+	} else {
+		Some(r.headers()?.clone())
+	};
+	let mut rows = RowProcessor::new(conf, headers);
+	rows.progressive = true;
+	if conf.scale_header {
+		writeln!(w, "{}", scale_header(conf))?;
+	}
+	let mut out = csv::WriterBuilder::new()
+		.delimiter(conf.output_delimiter.unwrap_or(b','))
+		.from_writer(w);
+
+	let mut accounts = HashMap::new();
+	let mut rec = csv::StringRecord::new();
+	while rows.read_record(&mut r, &mut rec, 0)? {
+		let line = rec.position().map_or(0, |p| p.line());
+		rows.process(&mut accounts, &rec, line, &mut |_| ())?;
+		if !rows.finalized_accounts.is_empty() {
+			for (cl, acc) in rows.finalized_accounts.drain(..) {
+				if !is_system(conf, cl) {
+					out.serialize(OutRow::new(cl, &acc, conf))?;
+				}
+			}
+			out.flush()?;
+		}
+	}
+	rows.finish()?;
+	for (cl, acc) in &accounts {
+		if !is_system(conf, *cl) {
+			out.serialize(OutRow::new(*cl, acc, conf))?;
+		}
+	}
+	out.flush()?;
+
+	Ok(rows.report)
+}
+
+/// Incremental processing of CSV input pushed in chunks of arbitrary size,
+/// such as from a socket.
+///
+/// Complete rows are applied as soon as they are pushed, so [Ingest::push]
+/// only returns once the engine has caught up with the input. Callers
+/// reading from a faster source are thereby throttled to the processing
+/// rate. Partial rows are buffered until the rest of them arrives.
+///
+/// Rows are split on line terminators, so quoted fields can not contain
+/// them.
+pub struct Ingest<'c> {
+	accounts: HashMap<u16, Account>,
+
+	/// `None` until the header row is received
+	rows: Option<RowProcessor<'c>>,
+
+	/// Input not yet terminated by a line terminator
+	buf: Vec<u8>,
+
+	/// Lines processed so far
+	line: u64,
+
+	conf: &'c Config,
+}
+
+impl<'c> Ingest<'c> {
+	/// Start ingestion with `accounts` as the initial state
+	pub fn new(accounts: HashMap<u16, Account>, conf: &'c Config) -> Self {
+		Self {
+			accounts,
+			rows: conf.no_header.then(|| RowProcessor::new(conf, None)),
+			buf: Vec::new(),
+			line: 0,
+			conf,
+		}
+	}
+
+	/// Apply all rows completed by `chunk`
+	pub fn push(&mut self, chunk: &[u8]) -> Result<(), ProcessError> {
+		self.buf.extend_from_slice(chunk);
+		if let Some(i) = self.buf.iter().rposition(|&b| b == b'\n') {
+			self.parse(i + 1)?;
+			self.buf.drain(..=i);
+		}
+		Ok(())
+	}
+
+	/// Apply the final row, if not terminated, and return the resulting
+	/// account state and report
+	pub fn finish(
+		mut self,
+	) -> Result<(HashMap<u16, Account>, Report), ProcessError> {
+		if !self.buf.is_empty() {
+			if self.conf.detect_truncation {
+				return Err(ProcessError::Truncated(self.line + 1));
+			}
+			self.parse(self.buf.len())?;
+		}
+		if let Some(rows) = &mut self.rows {
+			rows.finish()?;
+		}
+		Ok((
+			self.accounts,
+			self.rows.map(|r| r.report).unwrap_or_default(),
+		))
+	}
+
+	/// Apply the complete rows in the first `n` buffered bytes
+	fn parse(&mut self, n: usize) -> Result<(), ProcessError> {
+		let mut r = csv::ReaderBuilder::new()
+			.trim(csv::Trim::All)
+			.quote(self.conf.quote.unwrap_or(b'"'))
+			.has_headers(false)
+			.from_reader(LineLimit::new(&self.buf[..n], None));
+		let mut rec = csv::StringRecord::new();
+		if self.rows.is_none() && r.read_record(&mut rec)? {
+			self.rows = Some(RowProcessor::new(self.conf, Some(rec.clone())));
+		}
+		if let Some(rows) = &mut self.rows {
+			while rows.read_record(&mut r, &mut rec, self.line)? {
+				let line = self.line + rec.position().map_or(0, |p| p.line());
+				rows.process(&mut self.accounts, &rec, line, &mut |_| ())?;
+			}
+		}
+		self.line +=
+			self.buf[..n].iter().filter(|&&b| b == b'\n').count() as u64;
+		Ok(())
+	}
+}
+
+/// Least recently used order of registered deposits for
+/// [Config::deposit_cache] and the IDs of the latest evicted ones
+struct DepositCache {
+	/// Maximum number of registered and of remembered evicted deposits
+	capacity: usize,
+
+	/// Deposits by the sequence number of their latest use
+	used: BTreeMap<u64, (u16, u32)>,
+
+	/// Sequence number of the latest use of each deposit in [Self::used]
+	uses: HashMap<(u16, u32), u64>,
+
+	/// Next use sequence number
+	clock: u64,
+
+	/// Deposits evicted from the registry
+	evicted: HashSet<(u16, u32)>,
+
+	/// Deposits in [Self::evicted] in order of eviction
+	eviction_order: VecDeque<(u16, u32)>,
+}
+
+impl DepositCache {
+	fn new(capacity: usize) -> Self {
+		Self {
+			capacity,
+			used: BTreeMap::new(),
+			uses: HashMap::new(),
+			clock: 0,
+			evicted: HashSet::new(),
+			eviction_order: VecDeque::new(),
+		}
+	}
+
+	/// Record a use of deposit `tx` of `client`, making it the most recently
+	/// used one
+	fn touch(&mut self, client: u16, tx: u32) {
+		if let Some(prev) = self.uses.insert((client, tx), self.clock) {
+			self.used.remove(&prev);
+		}
+		self.used.insert(self.clock, (client, tx));
+		self.clock += 1;
+	}
+
+	/// Remove and return the least recently used deposit, if the tracked ones
+	/// and `pinned` deposits held aside by the caller exceed the capacity
+	fn pop_excess(&mut self, pinned: usize) -> Option<(u16, u32)> {
+		if self.used.len() + pinned <= self.capacity {
+			return None;
+		}
+		let (_, key) = self.used.pop_first()?;
+		self.uses.remove(&key);
+		Some(key)
+	}
+
+	/// Remember deposit `tx` of `client` as evicted, forgetting the earliest
+	/// evicted ones beyond the capacity
+	fn record_eviction(&mut self, client: u16, tx: u32) {
+		if self.evicted.insert((client, tx)) {
+			self.eviction_order.push_back((client, tx));
+		}
+		while self.eviction_order.len() > self.capacity {
+			if let Some(old) = self.eviction_order.pop_front() {
+				self.evicted.remove(&old);
+			}
+		}
+	}
+
+	/// Deposit `tx` of `client` is among the remembered evicted ones
+	fn was_evicted(&self, client: u16, tx: u32) -> bool {
+		self.evicted.contains(&(client, tx))
+	}
+}
+
+/// Applies parsed CSV records to accounts, keeping the state that carries
+/// between rows of one input
+struct RowProcessor<'c> {
+	conf: &'c Config,
+
+	/// Header row of the input, if any
+	headers: Option<csv::StringRecord>,
+
+	/// Index of the transaction type column
+	type_col: usize,
+
+	/// Highest transaction ID seen, if the deposit registry is bounded by
+	/// a dispute window
+	latest_tx: u32,
+
+	/// Deposits in order of arrival, if the deposit registry is bounded by
+	/// a dispute window
+	window: VecDeque<(u32, u16)>,
+
+	/// Use order of registered deposits, if the deposit registry is bounded
+	/// by [Config::deposit_cache]
+	deposit_cache: Option<DepositCache>,
+
+	/// Withdrawals kept by [Config::detect_withdrawal_disputes]
+	withdrawals: HashSet<(u16, u32)>,
+
+	/// Lines and deadlines of disputes of unknown deposits held by
+	/// [Config::dispute_grace]
+	early_disputes: HashMap<(u16, u32), (u64, u64)>,
+
+	/// Rows processed so far
+	seq: u64,
+
+	/// Deadlines, clients and transaction IDs of disputes in order of
+	/// opening, if disputes are resolved automatically
+	open_disputes: VecDeque<(u64, u16, u32)>,
+
+	/// Latest deadline of each deposit's dispute in [Self::open_disputes]
+	dispute_deadlines: HashMap<(u16, u32), u64>,
+
+	/// Type of the first deposit or withdrawal of each transaction ID, if in
+	/// strict mode
+	tx_types: HashMap<u32, TxType>,
+
+	/// Handle `finalize` rows of [process_progressive]
+	progressive: bool,
+
+	/// Clients, whose accounts were finalized
+	finalized: HashSet<u16>,
+
+	/// Finalized accounts not yet written
+	finalized_accounts: Vec<(u16, Account)>,
+
+	/// Client of the current group of rows with [Config::sorted_by_client]
+	current_client: Option<u16>,
+
+	/// Data rows read so far, including ones skipped by [Config::skip_rows]
+	records: u64,
+
+	/// Reading stopped after [Config::take_rows] rows before the end of input
+	taken: bool,
+
+	report: Report,
+}
+
+impl<'c> RowProcessor<'c> {
+	fn new(conf: &'c Config, headers: Option<csv::StringRecord>) -> Self {
+		Self {
+			conf,
+			type_col: headers
+				.as_ref()
+				.and_then(|h| h.iter().position(|f| f == "type"))
+				.unwrap_or(0),
+			headers,
+			latest_tx: 0,
+			window: VecDeque::new(),
+			deposit_cache: conf
+				.deposit_cache
+				.map(|cap| DepositCache::new(cap.get())),
+			withdrawals: HashSet::new(),
+			early_disputes: HashMap::new(),
+			seq: 0,
+			open_disputes: VecDeque::new(),
+			dispute_deadlines: HashMap::new(),
+			tx_types: HashMap::new(),
+			progressive: false,
+			finalized: HashSet::new(),
+			finalized_accounts: Vec::new(),
+			current_client: None,
+			records: 0,
+			taken: false,
+			report: Report::default(),
+		}
+	}
+
+	/// Apply record `rec` on `line` of the input to `accounts`
+	fn process(
+		&mut self,
+		accounts: &mut HashMap<u16, Account>,
+		rec: &csv::StringRecord,
+		line: u64,
+		on_transaction: &mut impl FnMut(&AppliedTx),
+	) -> Result<(), ProcessError> {
+		self.process_row(accounts, rec, line, on_transaction)?;
+		self.seq += 1;
+		self.expire_early_disputes(self.seq)?;
+		self.auto_resolve(accounts, line, on_transaction);
+		self.evict_deposits(accounts);
+		Ok(())
+	}
+
+	/// Reject the disputes held by [Config::dispute_grace] in the report at
+	/// the end of input
+	fn finish(&mut self) -> Result<(), ProcessError> {
+		self.expire_early_disputes(u64::MAX)
+	}
+
+	/// Reject the disputes held by [Config::dispute_grace] with deadlines
+	/// before row `seq`
+	fn expire_early_disputes(&mut self, seq: u64) -> Result<(), ProcessError> {
+		if self.early_disputes.is_empty() {
+			return Ok(());
+		}
+		let mut expired = self
+			.early_disputes
+			.extract_if(|_, (_, deadline)| *deadline < seq)
+			.map(|((client, tx), (line, _))| Rejection {
+				line,
+				typ: TxType::Dispute,
+				client,
+				tx,
+				reason: RejectReason::DisputeGraceExpired,
+			})
+			.collect::<Vec<_>>();
+		expired.sort_unstable_by_key(|rej| rej.line);
+		for rej in expired {
+			self.report
+				.tx_stats
+				.entry(TxType::Dispute)
+				.or_default()
+				.rejected += 1;
+			self.report.reject(self.conf, rej)?;
+		}
+		Ok(())
+	}
+
+	/// Evict the least recently used deposits beyond [Config::deposit_cache]
+	fn evict_deposits(&mut self, accounts: &mut HashMap<u16, Account>) {
+		let Some(cache) = &mut self.deposit_cache else {
+			return;
+		};
+
+		// Deposits under dispute are kept and marked as used again
+		let mut disputed = Vec::new();
+		while let Some((client, tx)) = cache.pop_excess(disputed.len()) {
+			let Some(acc) = accounts.get_mut(&client) else {
+				continue;
+			};
+			if let Entry::Occupied(e) = acc.deposits.entry(tx) {
+				if e.get().dispute_state == DisputeState::Initiated {
+					disputed.push((client, tx));
+				} else {
+					e.remove();
+					cache.record_eviction(client, tx);
+				}
+			}
+		}
+		for (client, tx) in disputed {
+			cache.touch(client, tx);
+		}
+	}
+
+	/// Resolve disputes left open for [Config::auto_resolve] rows after the
+	/// row on `line`
+	fn auto_resolve(
+		&mut self,
+		accounts: &mut HashMap<u16, Account>,
+		line: u64,
+		on_transaction: &mut impl FnMut(&AppliedTx),
+	) {
+		while let Some(&(deadline, client, tx)) = self.open_disputes.front() {
+			if deadline >= self.seq {
+				break;
+			}
+			self.open_disputes.pop_front();
+
+			// The deposit was disputed again after an explicit resolve and
+			// has a later deadline
+			if self.dispute_deadlines.get(&(client, tx)) != Some(&deadline) {
+				continue;
+			}
+			self.dispute_deadlines.remove(&(client, tx));
+
+			// Disputes already resolved or charged back are left as is
+			let Some(acc) = accounts.get_mut(&client).filter(|acc| {
+				acc.deposits
+					.get(&tx)
+					.is_some_and(|d| d.dispute_state == DisputeState::Initiated)
+			}) else {
+				continue;
+			};
+			let tx = Transaction::Resolve { tx, amount: None };
+			let before = (acc.available, acc.held, acc.applied);
+			if acc.apply_with(&tx, self.conf).is_ok() && acc.applied != before.2
+			{
+				on_transaction(&AppliedTx {
+					line,
+					client,
+					transaction: tx,
+					available_delta: acc.available - before.0,
+					held_delta: acc.held - before.1,
+					account: acc,
+				});
+			}
+		}
+	}
+
+	fn process_row(
+		&mut self,
+		accounts: &mut HashMap<u16, Account>,
+		rec: &csv::StringRecord,
+		line: u64,
+		on_transaction: &mut impl FnMut(&AppliedTx),
+	) -> Result<(), ProcessError> {
+		let conf = self.conf;
+		let expired = |tx: u32, latest_tx: u32| {
+			conf.dispute_window.is_some_and(|w| latest_tx - tx > w)
+		};
+
+		let row: InRow = match rec.deserialize(self.headers.as_ref()) {
+			Ok(row) => row,
+			Err(e) if conf.collect_errors => {
+				self.report.parse_errors.push(ParseError::new(line, &e));
+				return Ok(());
+			}
+			Err(e) => return Err(e.into()),
+		};
+
+		// Raw name of an unknown transaction type
+		let unknown_type = || rec.get(self.type_col).unwrap_or_default();
+		if self.progressive
+			&& row.typ == TxType::Unknown
+			&& unknown_type() == "finalize"
+		{
+			if self.finalized.insert(row.client) {
+				if let Some(acc) = accounts.remove(&row.client) {
+					self.finalized_accounts.push((row.client, acc));
+				}
+			}
+			return Ok(());
+		}
+		if self.progressive
+			&& conf.sorted_by_client
+			&& self.current_client != Some(row.client)
+		{
+			if self.finalized.contains(&row.client) {
+				return Err(ProcessError::Ungrouped {
+					line,
+					client: row.client,
+				});
+			}
+			if let Some(cl) = self.current_client.replace(row.client) {
+				self.finalized.insert(cl);
+				if let Some(acc) = accounts.remove(&cl) {
+					self.finalized_accounts.push((cl, acc));
+				}
+			}
+		}
+		if row.typ == TxType::Unknown
+			&& matches!(conf.unknown_tx, UnknownTxPolicy::Fail)
+		{
+			return self.parse_error(ParseError {
+				line,
+				message: format!(
+					"unknown transaction type: {}",
+					unknown_type()
+				),
+			});
+		}
+		let amount = match row.amount(conf) {
+			Ok(amount) => amount,
+			Err(message) => {
+				return self.parse_error(ParseError { line, message })
+			}
+		};
+		self.report.tx_stats.entry(row.typ).or_default().read += 1;
+
+		if conf.dispute_window.is_some() {
+			self.latest_tx = self.latest_tx.max(row.tx);
+			while let Some(&(tx, cl)) = self.window.front() {
+				if !expired(tx, self.latest_tx) {
+					break;
+				}
+				self.window.pop_front();
+				if let Some(acc) = accounts.get_mut(&cl) {
+					if let Entry::Occupied(e) = acc.deposits.entry(tx) {
+						if e.get().dispute_state != DisputeState::Initiated {
+							e.remove();
+						}
+					}
+				}
+			}
+		}
+
+		// Skip before the account is created, so shards only allocate their
+		// own clients
+		if !conf.clients.includes(row.client) {
+			if conf.report_skipped {
+				self.report.skipped.push(Rejection {
+					line,
+					typ: row.typ,
+					client: row.client,
+					tx: row.tx,
+					reason: RejectReason::ClientExcluded,
+				});
+			}
+			return Ok(());
+		}
+
+		if let (Some(eps), Some(amount)) = (conf.precision_warning, amount) {
+			let rounded =
+				to_minor(conf, amount) as f64 / units_per_major(conf) as f64;
+			if (amount - rounded).abs() > eps {
+				self.report.precision_warnings.push(PrecisionWarning {
+					line,
+					typ: row.typ,
+					client: row.client,
+					tx: row.tx,
+					amount,
+					rounded,
+				});
+			}
+		}
+
+		if self.finalized.contains(&row.client) {
+			return self.report.reject(
+				conf,
+				Rejection {
+					line,
+					typ: row.typ,
+					client: row.client,
+					tx: row.tx,
+					reason: RejectReason::AccountFinalized,
+				},
+			);
+		}
+
+		let acc = account_entry(accounts, row.client, conf);
+		acc.rows_seen += 1;
+
+		let mut out_of_order = false;
+		if conf.verify_tx_order
+			&& matches!(row.typ, TxType::Deposit | TxType::Withdrawal)
+		{
+			out_of_order = acc.last_tx.is_some_and(|tx| row.tx <= tx);
+			if !out_of_order {
+				acc.last_tx = Some(row.tx);
+			}
+		}
+
+		// Conflicting reuse is otherwise masked by the deposit registry only
+		// recording one of the transactions
+		let type_conflict = conf.is_strict()
+			&& matches!(row.typ, TxType::Deposit | TxType::Withdrawal)
+			&& *self.tx_types.entry(row.tx).or_insert(row.typ) != row.typ;
+
+		let res = if conf
+			.reserved_tx
+			.as_ref()
+			.is_some_and(|r| r.contains(&row.tx))
+		{
+			Err(RejectReason::ReservedTxId)
+		} else if row.client == 0 && conf.client_zero == ClientZero::Reject {
+			Err(RejectReason::ClientZero)
+		} else if out_of_order {
+			Err(RejectReason::OutOfOrder)
+		} else if type_conflict {
+			Err(RejectReason::TxTypeConflict)
+		} else if let Some(reason) =
+			sign_violation(row.typ, amount).filter(|_| conf.validate_signs)
+		{
+			Err(reason)
+		} else {
+			row.transaction(line, unknown_type(), amount)
+		};
+		let mut held = false;
+		let counted = acc.applied;
+		let res = res.and_then(|tx| {
+			// Deposits dropped from the registry would otherwise be ignored
+			// like any other unknown deposit
+			if let Transaction::Dispute { tx } = tx {
+				if acc.can_dispute(conf)
+					&& !acc.deposits.contains_key(&tx)
+					&& expired(tx, self.latest_tx)
+				{
+					return Err(RejectReason::DisputeWindowExpired);
+				}
+				if acc.can_dispute(conf)
+					&& !acc.deposits.contains_key(&tx)
+					&& self
+						.deposit_cache
+						.as_ref()
+						.is_some_and(|c| c.was_evicted(row.client, tx))
+				{
+					return Err(RejectReason::DepositEvicted);
+				}
+				if acc.can_dispute(conf)
+					&& !acc.deposits.contains_key(&tx)
+					&& self.withdrawals.contains(&(row.client, tx))
+				{
+					return Err(RejectReason::DisputeOnWithdrawalUnsupported);
+				}
+				if let Some(grace) = conf.dispute_grace {
+					if acc.can_dispute(conf) && !acc.deposits.contains_key(&tx)
+					{
+						self.early_disputes.insert(
+							(row.client, tx),
+							(line, self.seq + grace as u64),
+						);
+						held = true;
+						return Ok(());
+					}
+				}
+			}
+			let undisputed = |acc: &Account| {
+				acc.deposits.get(&row.tx).is_some_and(|d| {
+					d.dispute_state == DisputeState::NotInitiated
+				})
+			};
+			let opens_dispute = conf.auto_resolve.is_some()
+				&& matches!(tx, Transaction::Dispute { .. })
+				&& undisputed(acc);
+			let before = (acc.available, acc.held, acc.applied);
+			let clamped = match acc.apply_with(&tx, conf) {
+				Err(RejectReason::Overflow)
+					if conf.on_overflow == OverflowPolicy::ClampAndReport =>
+				{
+					true
+				}
+				res => {
+					res?;
+					false
+				}
+			};
+			if let Some(after) = conf.auto_resolve {
+				if opens_dispute && !undisputed(acc) {
+					let deadline = self.seq + after as u64;
+					self.open_disputes
+						.push_back((deadline, row.client, row.tx));
+					self.dispute_deadlines
+						.insert((row.client, row.tx), deadline);
+				}
+			}
+			if conf.dispute_window.is_some()
+				&& matches!(
+					tx,
+					Transaction::Deposit { .. }
+						| Transaction::Withdrawal { .. }
+				) && acc.deposits.contains_key(&row.tx)
+			{
+				self.window.push_back((row.tx, row.client));
+			}
+			if let Some(cache) = &mut self.deposit_cache {
+				if acc.deposits.contains_key(&row.tx) {
+					cache.touch(row.client, row.tx);
+				}
+			}
+			if conf.detect_withdrawal_disputes
+				&& !conf.dispute_withdrawals
+				&& matches!(tx, Transaction::Withdrawal { .. })
+			{
+				self.withdrawals.insert((row.client, row.tx));
+			}
+			if let Transaction::Adjustment { tx, amount, reason } = tx {
+				self.report.adjustments.push(Adjustment {
+					line,
+					client: row.client,
+					tx,
+					amount,
+					reason: reason.map(str::to_owned),
+				});
+			}
+			if acc.applied != before.2 {
+				on_transaction(&AppliedTx {
+					line,
+					client: row.client,
+					transaction: tx,
+					available_delta: acc.available - before.0,
+					held_delta: acc.held - before.1,
+					account: acc,
+				});
+			}
+			if clamped {
+				self.report.clamped.push(Rejection {
+					line,
+					typ: row.typ,
+					client: row.client,
+					tx: row.tx,
+					reason: RejectReason::Overflow,
+				});
+			}
+			Ok(())
+		});
+		// Held disputes are counted once applied or expired
+		let stats = self.report.tx_stats.entry(row.typ).or_default();
+		match res {
+			Ok(()) if held => (),
+			Ok(()) if acc.applied == counted => stats.ignored += 1,
+			Ok(()) => stats.applied += 1,
+			Err(_) => stats.rejected += 1,
+		}
+		if let Err(reason) = res {
+			self.report.reject(
+				conf,
+				Rejection {
+					line,
+					typ: row.typ,
+					client: row.client,
+					tx: row.tx,
+					reason,
+				},
+			)?;
+		}
+
+		// Dispute of the deposit, that preceded it
+		if res.is_ok() && row.typ == TxType::Deposit {
+			if let Some((line, _)) =
+				self.early_disputes.remove(&(row.client, row.tx))
+			{
+				let tx = Transaction::Dispute { tx: row.tx };
+				let before = (acc.available, acc.held, acc.applied);
+				let res = acc.apply_with(&tx, conf);
+				let stats =
+					self.report.tx_stats.entry(TxType::Dispute).or_default();
+				match res {
+					Ok(()) if acc.applied == before.2 => stats.ignored += 1,
+					Ok(()) => stats.applied += 1,
+					Err(_) => stats.rejected += 1,
+				}
+				match res {
+					Ok(()) if acc.applied == before.2 => (),
+					Ok(()) => on_transaction(&AppliedTx {
+						line,
+						client: row.client,
+						transaction: tx,
+						available_delta: acc.available - before.0,
+						held_delta: acc.held - before.1,
+						account: acc,
+					}),
+					Err(reason) => self.report.reject(
+						conf,
+						Rejection {
+							line,
+							typ: TxType::Dispute,
+							client: row.client,
+							tx: row.tx,
+							reason,
+						},
+					)?,
+				}
+			}
+		}
+		Ok(())
+	}
+
+	/// Read the next record of `r` into `rec`. `line_offset` is added to the
+	/// reader's line numbers.
+	///
+	/// With [Config::collect_errors] rows with a different field count than
+	/// the first one are recorded as unparsable and skipped instead of
+	/// aborting processing.
+	///
+	/// Rows outside of [Config::skip_rows] and [Config::take_rows] are
+	/// skipped.
+	fn read_record(
+		&mut self,
+		r: &mut csv::Reader<LineLimit<impl Read>>,
+		rec: &mut csv::StringRecord,
+		line_offset: u64,
+	) -> Result<bool, ProcessError> {
+		loop {
+			if self
+				.conf
+				.take_rows
+				.is_some_and(|n| self.records >= self.conf.skip_rows + n)
+			{
+				self.taken = true;
+				return Ok(false);
+			}
+
+			let res = r.read_record(rec);
+			if let Ok(true) = res {
+				self.records += 1;
+				if self.records <= self.conf.skip_rows {
+					continue;
+				}
+			}
+
+			// Oversized lines preceding the record
+			let before = match &res {
+				Ok(true) => rec.position(),
+				Ok(false) => None,
+				Err(e) => e.position(),
+			}
+			.map_or(u64::MAX, |p| p.line());
+			while let Some(line) = r.get_mut().oversized(before) {
+				self.parse_error(ParseError {
+					line: line + line_offset,
+					message: line_too_long(self.conf.max_line_bytes),
+				})?;
+			}
+
+			match res {
+				Err(e)
+					if self.conf.collect_errors
+						&& matches!(
+							e.kind(),
+							csv::ErrorKind::UnequalLengths { .. }
+						) =>
+				{
+					let line = e.position().map_or(0, |p| p.line());
+					self.parse_error(ParseError::new(line + line_offset, &e))?;
+				}
+				res => return Ok(res?),
+			}
+		}
+	}
+
+	/// Abort processing with the unparsable row error `e` or record it with
+	/// [Config::collect_errors]
+	fn parse_error(&mut self, e: ParseError) -> Result<(), ProcessError> {
+		if self.conf.collect_errors {
+			self.report.parse_errors.push(e);
+			return Ok(());
+		}
+		Err(ProcessError::Parse(e))
+	}
+}
+
+/// Reader wrapper that keeps the last byte read
+struct LastByte<R> {
+	inner: R,
+	last: Option<u8>,
+}
+
+impl<R> LastByte<R> {
+	fn new(inner: R) -> Self {
+		Self { inner, last: None }
+	}
+}
+
+impl<R: Read> Read for LastByte<R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let n = self.inner.read(buf)?;
+		if n != 0 {
+			self.last = Some(buf[n - 1]);
+		}
+		Ok(n)
+	}
+}
+
+/// Reader wrapper that replaces lines longer than [Config::max_line_bytes]
+/// with empty ones and records their line numbers. Lines are read in full
+/// before being passed on, so oversized ones are never buffered entirely.
+struct LineLimit<R> {
+	inner: io::BufReader<R>,
+
+	/// Maximum line length. `None` passes all input through as is.
+	max: Option<usize>,
+
+	/// Current line to pass on
+	line: Vec<u8>,
+
+	/// Bytes of [Self::line] already passed on
+	pos: usize,
+
+	/// Lines read so far
+	lines: u64,
+
+	/// Numbers of the oversized lines in input order
+	oversized: VecDeque<u64>,
+}
+
+impl<R: Read> LineLimit<R> {
+	fn new(inner: R, max: Option<usize>) -> Self {
+		Self {
+			inner: io::BufReader::new(inner),
+			max,
+			line: Vec::new(),
+			pos: 0,
+			lines: 0,
+			oversized: VecDeque::new(),
+		}
+	}
+
+	/// Inner reader
+	fn get_ref(&self) -> &R {
+		self.inner.get_ref()
+	}
+
+	/// Take the number of the next oversized line, if it precedes line
+	/// `before`
+	fn oversized(&mut self, before: u64) -> Option<u64> {
+		self.oversized.pop_front_if(|line| *line < before)
+	}
+
+	/// Read the next line into [Self::line], replacing it with an empty one,
+	/// if longer than `max`
+	fn next_line(&mut self, max: usize) -> io::Result<()> {
+		self.line.clear();
+		self.pos = 0;
+		let mut oversized = false;
+		loop {
+			let buf = self.inner.fill_buf()?;
+			if buf.is_empty() {
+				break;
+			}
+			let (n, terminated) = match buf.iter().position(|&b| b == b'\n') {
+				Some(i) => (i + 1, true),
+				None => (buf.len(), false),
+			};
+			if !oversized {
+				if self.line.len() + n - terminated as usize > max {
+					oversized = true;
+					self.line.clear();
+				} else {
+					self.line.extend_from_slice(&buf[..n]);
+				}
+			}
+			self.inner.consume(n);
+			if terminated {
+				break;
+			}
+		}
+
+		if oversized {
+			self.line.push(b'\n');
+		}
+		if !self.line.is_empty() {
+			self.lines += 1;
+		}
+		if oversized {
+			self.oversized.push_back(self.lines);
+		}
+		Ok(())
+	}
+}
+
+impl<R: Read> Read for LineLimit<R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let Some(max) = self.max else {
+			return self.inner.read(buf);
+		};
+		if self.pos == self.line.len() {
+			self.next_line(max)?;
+		}
+		let n = buf.len().min(self.line.len() - self.pos);
+		buf[..n].copy_from_slice(&self.line[self.pos..self.pos + n]);
+		self.pos += n;
+		Ok(n)
+	}
+}
+
+/// Writer wrapper that withholds a trailing line terminator until more output
+/// follows it, dropping the final one
+struct HoldNewline<W> {
+	inner: W,
+	pending: bool,
+}
+
+impl<W: Write> Write for HoldNewline<W> {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		if buf.is_empty() {
+			return Ok(0);
+		}
+		if self.pending {
+			self.inner.write_all(b"\n")?;
+		}
+		self.pending = buf.ends_with(b"\n");
+		let n = buf.len() - self.pending as usize;
+		self.inner.write_all(&buf[..n])?;
+		Ok(buf.len())
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.inner.flush()
+	}
+}
+
+/// Describe the applied transaction and the resulting state of its account
+/// for debugging, like
+/// `line 4: dispute of tx 1 for client 1: available 0.5000, held 1.0000,
+/// locked false`
+pub fn format_trace(applied: &AppliedTx, conf: &Config) -> String {
+	format!(
+		"line {}: {} of tx {} for client {}: available {}, held {}, locked {}",
+		applied.line,
+		applied.transaction.name(),
+		applied.transaction.tx(),
+		applied.client,
+		format_amount(conf, applied.account.available),
+		format_amount(conf, applied.account.held),
+		applied.account.locked
+	)
+}
+
+/// Write `rejections` to `w` as CSV with `line, client, tx, type, reason`
+/// columns for machine processing
+pub fn write_rejections(
+	w: &mut impl Write,
+	rejections: &[Rejection],
+) -> Result<(), ProcessError> {
+	let mut w = csv::Writer::from_writer(w);
+	w.write_record(["line", "client", "tx", "type", "reason"])?;
+	for rej in rejections {
+		w.write_record([
+			&rej.line.to_string(),
+			&rej.client.to_string(),
+			&rej.tx.to_string(),
+			rej.typ.as_str(),
+			rej.reason.as_str(),
+		])?;
+	}
+	w.flush()?;
+	Ok(())
+}
+
+/// Write the deposit registry of `acc` to `w` as CSV with
+/// `tx, amount, dispute_state` columns sorted by transaction ID for
+/// investigation
+pub fn write_ledger(
+	w: &mut impl Write,
+	acc: &Account,
+	conf: &Config,
+) -> Result<(), ProcessError> {
+	let mut deposits = acc.deposits.iter().collect::<Vec<_>>();
+	deposits.sort_unstable_by_key(|(tx, _)| **tx);
+
+	let mut w = csv::Writer::from_writer(w);
+	w.write_record(["tx", "amount", "dispute_state"])?;
+	for (tx, d) in deposits {
+		w.write_record([
+			&tx.to_string(),
+			&format_amount(conf, d.amount),
+			d.dispute_state.as_str(),
+		])?;
+	}
+	w.flush()?;
+	Ok(())
+}
+
+/// Statuses of `accounts` with the same fields as CSV output, sorted by client
+/// ID
+pub fn accounts_to_json(accounts: &HashMap<u16, Account>) -> serde_json::Value {
+	let mut clients = accounts.keys().copied().collect::<Vec<_>>();
+	clients.sort_unstable();
+	clients
+		.into_iter()
+		.map(|cl| accounts[&cl].json_row(cl))
+		.collect()
+}
+
+/// Write the statuses of locked accounts to `locked` and of all others to
+/// `active` in the configured output format
+pub fn write_accounts_split(
+	locked: &mut impl Write,
+	active: &mut impl Write,
+	accounts: HashMap<u16, Account>,
+	conf: &Config,
+) -> Result<(), ProcessError> {
+	let (l, a) = accounts.into_iter().partition(|(_, acc)| acc.locked);
+	write_accounts(locked, l, conf)?;
+	write_accounts(active, a, conf)
+}
+
+/// Write the account statuses to `w` in the configured output format
+pub fn write_accounts(
+	w: &mut impl Write,
+	accounts: HashMap<u16, Account>,
+	conf: &Config,
+) -> Result<(), ProcessError> {
+	let mut gz = None;
+	let w: &mut dyn Write = if conf.gzip_output {
+		gz.insert(GzEncoder::new(w, Compression::default()))
+	} else {
+		w
+	};
+
+	let mut held;
+	let w: &mut dyn Write = if conf.no_trailing_newline
+		&& conf.output_format != OutputFormat::Bincode
+	{
+		held = HoldNewline {
+			inner: w,
+			pending: false,
+		};
+		&mut held
+	} else {
+		w
+	};
+
+	let written = written_accounts(&accounts, conf);
+	let rows = written.iter().map(|(cl, acc)| OutRow::new(**cl, acc, conf));
+	match conf.output_format {
+		OutputFormat::Csv => {
+			let mut sink = CsvSink::new(w, conf)?;
+			for row in rows {
+				sink.write_account(&row)?;
+			}
+			sink.finish()?;
+		}
+		OutputFormat::Json => {
+			let mut sink = JsonSink::new(w);
+			for row in rows {
+				sink.write_account(&row)?;
+			}
+			sink.finish()?;
+		}
+		OutputFormat::JsonMap => {
+			serde_json::to_writer(
+				&mut &mut *w,
+				&rows
+					.map(|row| (row.client, row))
+					.collect::<BTreeMap<_, _>>(),
+			)?;
+			w.write_all(b"\n")?;
+		}
+		#[cfg(feature = "yaml")]
+		OutputFormat::Yaml => {
+			let mut rows = rows.collect::<Vec<_>>();
+			rows.sort_unstable_by_key(|row| row.client);
+			for row in rows {
+				writeln!(w, "{}:", row.client)?;
+
+				// JSON scalars are valid YAML flow scalars
+				let row: OrderedFields =
+					serde_json::from_str(&serde_json::to_string(&row)?)?;
+				for (k, v) in row.0 {
+					writeln!(w, "  {}: {}", k, v)?;
+				}
+			}
+		}
+		OutputFormat::Bincode => {
+			bincode::serde::encode_into_std_write(
+				&accounts,
+				&mut &mut *w,
+				bincode::config::standard(),
+			)?;
+		}
+	}
+
+	if let Some(gz) = gz {
+		gz.finish()?;
+	}
+	Ok(())
+}
+
+/// Accounts to write to the output in order
+fn written_accounts<'a>(
+	accounts: &'a HashMap<u16, Account>,
+	conf: &Config,
+) -> Vec<(&'a u16, &'a Account)> {
+	let mut written = accounts
+		.iter()
+		.filter(|(_, acc)| !(conf.suppress_empty && acc.is_empty()))
+		.filter(|(_, acc)| !(conf.suppress_inactive && acc.applied == 0))
+		.filter(|(cl, _)| !is_system(conf, **cl))
+		.collect::<Vec<_>>();
+	if conf.sort_first_seen {
+		written.sort_unstable_by_key(|(cl, acc)| (acc.first_seen, **cl));
+	}
+	written
+}
+
+/// Destination of account status rows, so embedders can write output formats
+/// not built into the crate
+pub trait OutputSink {
+	/// Write the status row of an account
+	fn write_account(&mut self, row: &OutRow) -> Result<(), ProcessError>;
+
+	/// Complete the output after all rows were written
+	fn finish(self) -> Result<(), ProcessError>;
+}
+
+/// [OutputSink] writing CSV with a header row, as used by
+/// [OutputFormat::Csv].
+///
+/// Writes the [Config::scale_header], [Config::grand_total] and
+/// [Config::checksum] rows and uses [Config::output_delimiter].
+/// [Config::gzip_output] and [Config::no_trailing_newline] apply to the writer
+/// of [write_accounts] and are not done by the sink.
+pub struct CsvSink<'c, W: Write> {
+	w: csv::Writer<W>,
+	conf: &'c Config,
+
+	/// Sums of the available, held and total amounts of the written rows.
+	/// Summed as i128, which can not overflow even with every client holding
+	/// the maximum balance.
+	sums: [i128; 3],
+
+	/// Rows written so far
+	written: usize,
+}
+
+impl<'c, W: Write> CsvSink<'c, W> {
+	/// Sink writing to `w` with the CSV output options of `conf`. Writes the
+	/// [Config::scale_header] line right away.
+	pub fn new(mut w: W, conf: &'c Config) -> Result<Self, ProcessError> {
+		if conf.scale_header {
+			writeln!(w, "{}", scale_header(conf))?;
+		}
+		Ok(Self {
+			w: csv::WriterBuilder::new()
+				// Checksum row has fewer fields
+				.flexible(true)
+				// Fields containing the delimiter, such as amounts with
+				// a matching decimal separator, are quoted
+				.delimiter(conf.output_delimiter.unwrap_or(b','))
+				.from_writer(w),
+			conf,
+			sums: [0; 3],
+			written: 0,
+		})
+	}
+}
+
+impl<W: Write> OutputSink for CsvSink<'_, W> {
+	fn write_account(&mut self, row: &OutRow) -> Result<(), ProcessError> {
+		for (sum, amount) in self.sums.iter_mut().zip(row.amounts) {
+			*sum += i128::from(amount);
+		}
+		self.written += 1;
+		Ok(self.w.serialize(row)?)
+	}
+
+	fn finish(mut self) -> Result<(), ProcessError> {
+		let conf = self.conf;
+		let [available, held, total] = self.sums;
+		if conf.grand_total {
+			let columns = conf.columns.as_deref().unwrap_or(&OutColumn::ALL);
+			self.w.write_record(columns.iter().map(|col| match col {
+				OutColumn::Client => "TOTAL".to_owned(),
+				OutColumn::Available => format_amount(conf, available),
+				OutColumn::Held => format_amount(conf, held),
+				OutColumn::Total => format_amount(conf, total),
+				OutColumn::Locked => String::new(),
+			}))?;
+		}
+		if conf.checksum {
+			self.w.write_record([
+				"checksum",
+				&format_amount(conf, total),
+				&self.written.to_string(),
+			])?;
+		}
+		Ok(self.w.flush()?)
+	}
+}
+
+/// [OutputSink] writing a JSON array of account objects, as used by
+/// [OutputFormat::Json]
+pub struct JsonSink<W: Write> {
+	w: W,
+
+	/// Rows written so far
+	written: usize,
+}
+
+impl<W: Write> JsonSink<W> {
+	/// Sink writing to `w`
+	pub fn new(w: W) -> Self {
+		Self { w, written: 0 }
+	}
+}
+
+impl<W: Write> OutputSink for JsonSink<W> {
+	fn write_account(&mut self, row: &OutRow) -> Result<(), ProcessError> {
+		self.w
+			.write_all(if self.written == 0 { b"[" } else { b"," })?;
+		serde_json::to_writer(&mut self.w, row)?;
+		self.written += 1;
+		Ok(())
+	}
+
+	fn finish(mut self) -> Result<(), ProcessError> {
+		if self.written == 0 {
+			self.w.write_all(b"[")?;
+		}
+		self.w.write_all(b"]\n")?;
+		Ok(self.w.flush()?)
+	}
+}
+
+/// Write the account statuses to `sink`.
+///
+/// Accounts are filtered and ordered like by [write_accounts]. Options of
+/// the built-in output formats, such as [Config::checksum], are applied by
+/// their sinks, like [CsvSink].
+pub fn write_accounts_to(
+	mut sink: impl OutputSink,
+	accounts: &HashMap<u16, Account>,
+	conf: &Config,
+) -> Result<(), ProcessError> {
+	for (cl, acc) in written_accounts(accounts, conf) {
+		sink.write_account(&OutRow::new(*cl, acc, conf))?;
+	}
+	sink.finish()
+}
+
+/// Violation of the amount sign conventions of [Config::validate_signs] by
+/// a transaction of type `typ` with `amount`
+fn sign_violation(typ: TxType, amount: Option<f64>) -> Option<RejectReason> {
+	match (typ, amount) {
+		(TxType::Deposit | TxType::Withdrawal, Some(a))
+			if a.is_nan() || a <= 0.0 =>
+		{
+			Some(RejectReason::NonPositiveAmount)
+		}
+		(TxType::Dispute | TxType::Resolve | TxType::Chargeback, Some(_)) => {
+			Some(RejectReason::UnexpectedAmount)
+		}
+		_ => None,
+	}
+}
+
+/// Account of `client` is the system account of [ClientZero::System]
+fn is_system(conf: &Config, client: u16) -> bool {
+	client == 0 && conf.client_zero == ClientZero::System
+}
+
+/// Fields of a JSON object in their order of appearance
+#[cfg(feature = "yaml")]
+struct OrderedFields(Vec<(String, serde_json::Value)>);
+
+#[cfg(feature = "yaml")]
+impl<'de> Deserialize<'de> for OrderedFields {
+	fn deserialize<D: serde::Deserializer<'de>>(
+		d: D,
+	) -> Result<Self, D::Error> {
+		struct Visitor;
+
+		impl<'de> serde::de::Visitor<'de> for Visitor {
+			type Value = OrderedFields;
+
+			fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+				f.write_str("an object")
+			}
+
+			fn visit_map<A: serde::de::MapAccess<'de>>(
+				self,
+				mut map: A,
+			) -> Result<Self::Value, A::Error> {
+				let mut fields = Vec::new();
+				while let Some(field) = map.next_entry()? {
+					fields.push(field);
+				}
+				Ok(OrderedFields(fields))
+			}
+		}
+
+		d.deserialize_map(Visitor)
+	}
+}
+
+/// A row of the input CSV file
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct InRow {
+	/// Transaction type
+	#[serde(rename = "type")]
+	pub typ: TxType,
+
+	/// Client ID
+	pub client: u16,
+
+	/// Transaction ID
+	pub tx: u32,
+
+	/// Transaction amount as written in the input
+	pub amount: Option<String>,
+
+	/// Reason of an adjustment from an optional `reason` column
+	#[serde(default)]
+	pub reason: Option<String>,
+}
+
+impl InRow {
+	/// Transaction amount in major currency units parsed with
+	/// [Config::amount_parser].
+	/// Whitespace-only amounts are missing like empty ones.
+	fn amount(&self, conf: &Config) -> Result<Option<f64>, String> {
+		self.amount
+			.as_deref()
+			.map(str::trim)
+			.filter(|s| !s.is_empty())
+			.map(|s| {
+				let s = match &conf.amount_prefix {
+					Some(prefix) => {
+						let s = s.strip_prefix(prefix.as_str()).unwrap_or(s);
+						match conf.decimal_separator {
+							Some(sep) if sep != '.' => {
+								Cow::Owned(s.replace(sep, "."))
+							}
+							_ => Cow::Borrowed(s),
+						}
+					}
+					None => Cow::Borrowed(s),
+				};
+				match &conf.amount_parser {
+					Some(p) => p.parse(&s),
+					None => DecimalAmountParser.parse(&s),
+				}
+			})
+			.transpose()
+	}
+
+	/// Transaction described by the row. `line` and `typ` are the row's line
+	/// in the input and its raw transaction type name. `amount` is the parsed
+	/// transaction amount.
+	fn transaction<'a>(
+		&'a self,
+		line: u64,
+		typ: &'a str,
+		amount: Option<f64>,
+	) -> Result<Transaction<'a>, RejectReason> {
+		let tx = self.tx;
+		Ok(match (self.typ, amount) {
+			(TxType::Deposit, Some(amount)) => {
+				Transaction::Deposit { tx, amount }
+			}
+			(TxType::Withdrawal, Some(amount)) => {
+				Transaction::Withdrawal { tx, amount }
+			}
+			(TxType::Adjustment, Some(amount)) => Transaction::Adjustment {
+				tx,
+				amount,
+				reason: self.reason.as_deref(),
+			},
+			// Present, but empty amount field would otherwise silently drop
+			// the transaction
+			(
+				TxType::Deposit | TxType::Withdrawal | TxType::Adjustment,
+				None,
+			) => return Err(RejectReason::MissingAmount),
+			(TxType::Dispute, _) => Transaction::Dispute { tx },
+			(TxType::Resolve, amount) => Transaction::Resolve { tx, amount },
+			(TxType::Chargeback, amount) => {
+				Transaction::Chargeback { tx, amount }
+			}
+			(TxType::Unknown, amount) => Transaction::Unknown(UnknownTx {
+				line,
+				typ,
+				client: self.client,
+				tx,
+				amount,
+			}),
+		})
+	}
+}
+
+/// Status row of an account as written to the output
+pub struct OutRow<'a> {
+	/// Base columns to write in order
+	pub columns: &'a [OutColumn],
+
+	/// Client ID
+	pub client: u16,
+
+	/// Digits to zero-pad the client ID to. `None` writes it as a number.
+	pub client_width: Option<usize>,
+
+	/// Available amount in units set by [Config::minor_units]
+	pub available: String,
+
+	/// Held amount in units set by [Config::minor_units]
+	pub held: String,
+
+	/// Total amount in units set by [Config::minor_units]
+	pub total: String,
+
+	/// Account locked due to a chargeback. No more withdrawals are possible.
+	pub locked: bool,
+
+	/// Representation of [Self::locked]
+	pub bool_style: BoolStyle,
+
+	/// Transaction, whose chargeback locked the account.
+	/// Outer `None` omits the extended column.
+	pub locked_reason: Option<Option<u32>>,
+
+	/// Type of the latest transaction applied to the account.
+	/// Outer `None` omits the extended column.
+	pub last_tx_type: Option<Option<TxType>>,
+
+	/// Transactions applied to the account. `None` omits the extended column.
+	pub tx_count: Option<u64>,
+
+	/// Input rows of the client. `None` omits the extended column.
+	pub rows_seen: Option<u64>,
+
+	/// Currency label of the amounts. `None` omits the column.
+	pub currency: Option<&'a str>,
+
+	/// Available amount in minor units. `None` omits the audit column.
+	pub available_minor: Option<i64>,
+
+	/// Held amount in minor units. `None` omits the audit column.
+	pub held_minor: Option<i64>,
+
+	/// Total amount in minor units. `None` omits the audit column.
+	pub total_minor: Option<i64>,
+
+	/// Available, held and total amounts in minor units for the summary rows
+	/// of [CsvSink]
+	amounts: [i64; 3],
+}
+
+impl<'a> OutRow<'a> {
+	fn new(client: u16, acc: &Account, conf: &'a Config) -> Self {
+		let ext = conf.extended_output;
+		Self {
+			columns: conf.columns.as_deref().unwrap_or(&OutColumn::ALL),
+			client,
+			client_width: conf.client_width,
+			available: format_amount(conf, acc.available),
+			held: format_amount(conf, acc.held),
+			total: format_amount(conf, acc.total(conf)),
+			locked: acc.locked,
+			bool_style: conf.bool_style,
+			locked_reason: ext.then_some(acc.locked_reason),
+			last_tx_type: ext.then_some(acc.last_tx_type),
+			tx_count: ext.then_some(acc.applied),
+			rows_seen: ext.then_some(acc.rows_seen),
+			currency: conf.currency.as_deref(),
+			available_minor: conf.audit_columns.then_some(acc.available),
+			held_minor: conf.audit_columns.then_some(acc.held),
+			total_minor: conf.audit_columns.then(|| acc.total(conf)),
+			amounts: [acc.available, acc.held, acc.total(conf)],
+		}
+	}
+}
+
+impl Serialize for OutRow<'_> {
+	fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+		use serde::ser::SerializeStruct;
+
+		let optional = [
+			self.locked_reason.is_some(),
+			self.last_tx_type.is_some(),
+			self.tx_count.is_some(),
+			self.rows_seen.is_some(),
+			self.currency.is_some(),
+			self.available_minor.is_some(),
+			self.held_minor.is_some(),
+			self.total_minor.is_some(),
+		];
+		let mut row = s.serialize_struct(
+			"OutRow",
+			self.columns.len() + optional.iter().filter(|o| **o).count(),
+		)?;
+		for col in self.columns {
+			let name = col.as_str();
+			match col {
+				OutColumn::Client => match self.client_width {
+					Some(w) => row.serialize_field(
+						name,
+						&format!("{:0w$}", self.client, w = w),
+					)?,
+					None => row.serialize_field(name, &self.client)?,
+				},
+				OutColumn::Available => {
+					row.serialize_field(name, &self.available)?
+				}
+				OutColumn::Held => row.serialize_field(name, &self.held)?,
+				OutColumn::Total => row.serialize_field(name, &self.total)?,
+				OutColumn::Locked => match self.bool_style {
+					BoolStyle::TrueFalse => {
+						row.serialize_field(name, &self.locked)?
+					}
+					BoolStyle::OneZero => {
+						row.serialize_field(name, &u8::from(self.locked))?
+					}
+					BoolStyle::YesNo => row.serialize_field(
+						name,
+						if self.locked { "yes" } else { "no" },
+					)?,
+				},
+			}
+		}
+
+		// Columns enabled by their own options follow the base ones
+		if let Some(v) = &self.locked_reason {
+			row.serialize_field("locked_reason", v)?;
+		}
+		if let Some(v) = &self.last_tx_type {
+			row.serialize_field("last_tx_type", v)?;
+		}
+		if let Some(v) = &self.tx_count {
+			row.serialize_field("tx_count", v)?;
+		}
+		if let Some(v) = &self.rows_seen {
+			row.serialize_field("rows_seen", v)?;
+		}
+		if let Some(v) = &self.currency {
+			row.serialize_field("currency", v)?;
+		}
+		if let Some(v) = &self.available_minor {
+			row.serialize_field("available_minor", v)?;
+		}
+		if let Some(v) = &self.held_minor {
+			row.serialize_field("held_minor", v)?;
+		}
+		if let Some(v) = &self.total_minor {
+			row.serialize_field("total_minor", v)?;
+		}
+		row.end()
+	}
+}
+
+/// Supported transactions types
+#[derive(
+	Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum TxType {
+	Deposit,
+	Withdrawal,
+	Dispute,
+	Resolve,
+	Chargeback,
+
+	/// Administrative correction of the available funds
+	Adjustment,
+
+	/// Any other transaction type. Handled according to [Config::unknown_tx].
+	#[serde(other)]
+	Unknown,
+}
+
+impl TxType {
+	/// Name of the transaction type as used in the input
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Self::Deposit => "deposit",
+			Self::Withdrawal => "withdrawal",
+			Self::Dispute => "dispute",
+			Self::Resolve => "resolve",
+			Self::Chargeback => "chargeback",
+			Self::Adjustment => "adjustment",
+			Self::Unknown => "unknown",
+		}
+	}
+}
+
+/// Single transaction to apply to an [Account] with [Account::apply].
+/// Amounts are in major currency units.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transaction<'a> {
+	/// Credit `amount` to the account
+	Deposit { tx: u32, amount: f64 },
+
+	/// Debit `amount` from the account, if sufficient funds are available
+	Withdrawal { tx: u32, amount: f64 },
+
+	/// Hold the funds of deposit `tx`
+	Dispute { tx: u32 },
+
+	/// Release the held funds of disputed deposit `tx` and end the dispute.
+	/// `amount` releases only part of the disputed funds and keeps the rest
+	/// held under the ongoing dispute.
+	Resolve { tx: u32, amount: Option<f64> },
+
+	/// Withdraw the held funds of disputed deposit `tx` and lock the account,
+	/// unless [Config::no_lock_on_chargeback] is set.
+	/// `amount` charges back only part of the disputed funds.
+	Chargeback { tx: u32, amount: Option<f64> },
+
+	/// Credit signed `amount` to the available funds with
+	/// [Config::allow_admin], regardless of the account flags, but never
+	/// below zero. Adjustments can not be disputed.
+	Adjustment {
+		tx: u32,
+		amount: f64,
+		reason: Option<&'a str>,
+	},
+
+	/// Transaction of an unknown type. Handled according to
+	/// [Config::unknown_tx].
+	Unknown(UnknownTx<'a>),
+}
+
+impl Transaction<'_> {
+	/// Transaction type name as used in the input
+	pub fn name(&self) -> &str {
+		match self {
+			Self::Deposit { .. } => TxType::Deposit.as_str(),
+			Self::Withdrawal { .. } => TxType::Withdrawal.as_str(),
+			Self::Dispute { .. } => TxType::Dispute.as_str(),
+			Self::Resolve { .. } => TxType::Resolve.as_str(),
+			Self::Chargeback { .. } => TxType::Chargeback.as_str(),
+			Self::Adjustment { .. } => TxType::Adjustment.as_str(),
+			Self::Unknown(tx) => tx.typ,
+		}
+	}
+
+	/// Transaction type
+	pub fn typ(&self) -> TxType {
+		match self {
+			Self::Deposit { .. } => TxType::Deposit,
+			Self::Withdrawal { .. } => TxType::Withdrawal,
+			Self::Dispute { .. } => TxType::Dispute,
+			Self::Resolve { .. } => TxType::Resolve,
+			Self::Chargeback { .. } => TxType::Chargeback,
+			Self::Adjustment { .. } => TxType::Adjustment,
+			Self::Unknown(_) => TxType::Unknown,
+		}
+	}
+
+	/// Transaction ID
+	pub fn tx(&self) -> u32 {
+		match *self {
+			Self::Deposit { tx, .. }
+			| Self::Withdrawal { tx, .. }
+			| Self::Dispute { tx }
+			| Self::Resolve { tx, .. }
+			| Self::Chargeback { tx, .. }
+			| Self::Adjustment { tx, .. } => tx,
+			Self::Unknown(UnknownTx { tx, .. }) => tx,
+		}
+	}
+}
+
+// State of a possibly initiated dispute for a deposit transaction
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisputeState {
+	NotInitiated,
+	Initiated,
+	ChargedBack,
+}
+
+impl DisputeState {
+	/// Machine-readable name of the state
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Self::NotInitiated => "not_initiated",
+			Self::Initiated => "initiated",
+			Self::ChargedBack => "charged_back",
+		}
+	}
+}
+
+/// Deposit transaction state and amount.
+/// Stored for dispute resolution purposes only.
+///
+/// Withdrawals recorded with [Config::dispute_withdrawals] have negative
+/// amounts. Their disputes hold no funds and their chargebacks credit the
+/// available funds.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Deposit {
+	// State of a possibly initiated dispute for the transaction
+	pub dispute_state: DisputeState,
+
+	/// Transaction amount in minor units.
+	pub amount: i64,
+
+	/// Amount held under the current dispute in minor units
+	pub disputed: i64,
+
+	/// Amount already charged back by partial chargebacks in minor units
+	pub charged_back: i64,
+
+	/// Amount already released by partial resolves of the current dispute in
+	/// minor units
+	pub released: i64,
+
+	/// Part of the disputed amount not held with [DisputeShortfall::Clamp]
+	/// in minor units
+	pub shortfall: i64,
+}
+
+impl Deposit {
+	/// Undisputed deposit of `amount` minor units
+	pub fn new(amount: i64) -> Self {
+		Self {
+			dispute_state: DisputeState::NotInitiated,
+			amount,
+			disputed: 0,
+			charged_back: 0,
+			released: 0,
+			shortfall: 0,
+		}
+	}
+
+	/// Disputed, charged back and released amounts add up to the amount of
+	/// the deposit
+	fn is_consistent(&self) -> bool {
+		self.disputed
+			.checked_add(self.charged_back)
+			.and_then(|a| a.checked_add(self.released))
+			== Some(self.amount)
+	}
+
+	/// Settle the shortfall covering up to `amount` of the disputed funds
+	/// being released or charged back and return the settled part
+	fn settle_shortfall(&mut self, amount: i64) -> i64 {
+		let owed = self.owed(amount);
+		self.shortfall -= owed;
+		owed
+	}
+
+	/// Part of the shortfall [Deposit::settle_shortfall] would settle for
+	/// `amount`, without settling it
+	fn owed(&self, amount: i64) -> i64 {
+		amount.clamp(0, self.shortfall)
+	}
+
+	/// Part of `amount` of the disputed funds held for the transaction.
+	/// Disputed withdrawals hold nothing, as their funds have already left
+	/// the account.
+	fn held_share(&self, amount: i64) -> i64 {
+		if self.amount < 0 {
+			0
+		} else {
+			amount - self.owed(amount)
+		}
+	}
+}
+
+/// Current state of a client's account
+#[derive(Serialize, Deserialize, Default, Clone, Debug, PartialEq, Eq)]
+pub struct Account {
+	/// Account locked due to a chargeback. No more withdrawals are possible.
+	pub locked: bool,
+
+	/// Transaction, whose chargeback locked the account
+	pub locked_reason: Option<u32>,
+
+	/// Account frozen by an administrator. Never set by transactions.
+	pub frozen: bool,
+
+	/// Latest deposit or withdrawal transaction ID.
+	/// Only tracked with [Config::verify_tx_order].
+	pub last_tx: Option<u32>,
+
+	/// Sequence number of the account in order of creation
+	pub first_seen: u64,
+
+	/// Funds currently available for withdrawal in minor currency units
+	pub available: i64,
+
+	/// Funds currently held from withdrawal in minor currency units
+	pub held: i64,
+
+	/// Disputed funds that could not be held, as they were already
+	/// withdrawn, in minor currency units. Only tracked with
+	/// [DisputeShortfall::Clamp].
+	pub shortfall: i64,
+
+	/// Deposit transaction registry by transaction ID
+	pub deposits: HashMap<u32, Deposit>,
+
+	/// Transactions that changed the balances, the locked flag, the deposit
+	/// registry of the account or the dispute state of a deposit
+	pub applied: u64,
+
+	/// Type of the latest of [Self::applied]
+	pub last_tx_type: Option<TxType>,
+
+	/// Input rows of the client, including rejected and ignored ones
+	pub rows_seen: u64,
+}
+
+impl Account {
+	/// Transactions blocked by the flags set on the account
+	fn blocked(&self, conf: &Config) -> Blocked {
+		let mut b = Blocked::default();
+		if self.locked {
+			b = b.union(conf.flag_policy.locked);
+		}
+		if self.frozen {
+			b = b.union(conf.flag_policy.frozen);
+		}
+		b
+	}
+
+	/// Total funds of the account in minor currency units
+	fn total(&self, conf: &Config) -> i64 {
+		match conf.accounting {
+			// Saturating, as both can be clamped with
+			// [OverflowPolicy::ClampAndReport]
+			Accounting::MoveToHeld => self.available.saturating_add(self.held),
+			Accounting::Annotate => self.available,
+		}
+	}
+
+	/// Status of the account with the same fields as CSV output, except for
+	/// the client ID
+	pub fn to_json(&self) -> serde_json::Value {
+		let mut v = self.json_row(0);
+		if let serde_json::Value::Object(fields) = &mut v {
+			fields.remove("client");
+		}
+		v
+	}
+
+	/// Status of the account of `client` with the same fields as CSV output
+	fn json_row(&self, client: u16) -> serde_json::Value {
+		serde_json::to_value(OutRow::new(client, self, &Config::default()))
+			.expect("output row serializes to JSON")
+	}
+
+	/// Account has no funds and is not locked
+	fn is_empty(&self) -> bool {
+		self.available == 0 && self.held == 0 && !self.locked
+	}
+
+	/// Deposits can be made to the account
+	pub fn can_deposit(&self, conf: &Config) -> bool {
+		!self.blocked(conf).deposits
+	}
+
+	/// Withdrawals can be made from the account
+	pub fn can_withdraw(&self, conf: &Config) -> bool {
+		!self.blocked(conf).withdrawals
+	}
+
+	/// Funds that can currently be withdrawn in minor currency units. Zero,
+	/// if withdrawals are blocked by the account's flags or the available
+	/// funds are overdrawn, such as by a dispute of withdrawn funds.
+	pub fn withdrawable(&self, conf: &Config) -> i64 {
+		if self.can_withdraw(conf) {
+			self.available.max(0)
+		} else {
+			0
+		}
+	}
+
+	/// Disputes, resolves and chargebacks can be made on the account
+	pub fn can_dispute(&self, conf: &Config) -> bool {
+		!self.blocked(conf).disputes
+	}
+
+	/// Combine the state of the same client's account from another shard into
+	/// this one as described by [merge_accounts]
+	fn merge(&mut self, other: Account) {
+		self.locked |= other.locked;
+		self.locked_reason = self.locked_reason.or(other.locked_reason);
+		self.frozen |= other.frozen;
+		self.last_tx = self.last_tx.max(other.last_tx);
+		self.available += other.available;
+		self.held += other.held;
+		self.shortfall += other.shortfall;
+		self.first_seen = self.first_seen.min(other.first_seen);
+		self.applied += other.applied;
+		self.last_tx_type = other.last_tx_type.or(self.last_tx_type);
+		self.rows_seen += other.rows_seen;
+		for (tx, d) in other.deposits {
+			self.deposits.entry(tx).or_insert(d);
+		}
+	}
+
+	/// Apply a single transaction to the account with the default [Config]
+	pub fn apply(&mut self, tx: &Transaction) -> Result<(), RejectReason> {
+		self.apply_with(tx, &Config::default())
+	}
+
+	/// Apply a single transaction to the account.
+	///
+	/// Transactions blocked by the account flags and ones referencing
+	/// unknown deposits are ignored.
+	pub fn apply_with(
+		&mut self,
+		tx: &Transaction,
+		conf: &Config,
+	) -> Result<(), RejectReason> {
+		let annotate = conf.accounting == Accounting::Annotate;
+		let mut clamped = false;
+		let effect = |acc: &Self| {
+			(
+				acc.available,
+				acc.held,
+				acc.locked,
+				acc.deposits.len(),
+				acc.deposits.get(&tx.tx()).map(|d| d.dispute_state),
+			)
+		};
+		let before = effect(self);
+		let res = match *tx {
+			// Checked before conversion to minor units, which could
+			// otherwise overflow
+			Transaction::Deposit { amount, .. }
+			| Transaction::Withdrawal { amount, .. }
+				if conf.max_amount.is_some_and(|max| amount.abs() > max) =>
+			{
+				Err(RejectReason::AmountTooLarge)
+			}
+			Transaction::Deposit { amount, .. }
+				if conf.min_deposit.is_some_and(|min| amount < min) =>
+			{
+				Err(RejectReason::BelowMinDeposit)
+			}
+			Transaction::Deposit { tx, amount } if self.can_deposit(conf) => {
+				let amount = checked_minor(conf, amount)?;
+				self.available = clamp_overflow(
+					conf,
+					self.available.checked_add(amount),
+					self.available.saturating_add(amount),
+					&mut clamped,
+				)?;
+				self.deposits.insert(tx, Deposit::new(amount));
+				Ok(())
+			}
+			// The task definition did not specify what exactly locking an
+			// account entails.The term "freeze" was also used to describe
+			// locking, so I went with the Investopedia  definition of
+			// allowing deposits, but not withdrawals.
+			// Further disputes and chargebacks are also allowed on locked
+			// accounts, based on my understanding of what the business
+			// logic should be in those cases.
+			Transaction::Withdrawal { tx, amount }
+				if self.can_withdraw(conf) =>
+			{
+				let amount = checked_minor(conf, amount)?;
+				if conf.dispute_withdrawals && self.deposits.contains_key(&tx) {
+					return Err(RejectReason::DuplicateTx);
+				}
+				if amount <= self.withdrawable(conf) {
+					let residual = self.available.saturating_sub(amount);
+					if residual > 0
+						&& conf
+							.min_residual
+							.is_some_and(|min| residual < to_minor(conf, min))
+					{
+						return Err(RejectReason::DustBalance);
+					}
+					self.available = clamp_overflow(
+						conf,
+						self.available.checked_sub(amount),
+						self.available.saturating_sub(amount),
+						&mut clamped,
+					)?;
+					if conf.dispute_withdrawals {
+						self.deposits.insert(tx, Deposit::new(-amount));
+					}
+				} else if conf.report_refused_withdrawals {
+					return Err(RejectReason::InsufficientFunds);
+				}
+				Ok(())
+			}
+			Transaction::Withdrawal { .. }
+				if conf.report_refused_withdrawals =>
+			{
+				Err(RejectReason::LockedAccount)
+			}
+			Transaction::Dispute { tx } if self.can_dispute(conf) => {
+				if let Some(d) = self.deposits.get_mut(&tx) {
+					if matches!(d.dispute_state, DisputeState::NotInitiated) {
+						let disputed = d.amount - d.charged_back;
+						let shortfall = if !annotate
+							&& conf.dispute_shortfall
+								!= DisputeShortfall::AllowNegative
+						{
+							(disputed - self.available.max(0)).max(0)
+						} else {
+							0
+						};
+						if shortfall != 0
+							&& conf.dispute_shortfall
+								== DisputeShortfall::Reject
+						{
+							return Err(
+								RejectReason::InsufficientAvailableForDispute,
+							);
+						}
+
+						// Computed before modifying any state, so an overflow
+						// leaves the account as is. Disputed withdrawals hold
+						// nothing.
+						let held = if d.amount < 0 {
+							0
+						} else {
+							disputed - shortfall
+						};
+						let available = if annotate {
+							self.available
+						} else {
+							clamp_overflow(
+								conf,
+								self.available.checked_sub(held),
+								self.available.saturating_sub(held),
+								&mut clamped,
+							)?
+						};
+						let held = clamp_overflow(
+							conf,
+							self.held.checked_add(held),
+							self.held.saturating_add(held),
+							&mut clamped,
+						)?;
+						if held > self.held
+							&& conf
+								.max_held
+								.is_some_and(|max| held > to_minor(conf, max))
+						{
+							return Err(RejectReason::HoldLimitExceeded);
+						}
+
+						d.dispute_state = DisputeState::Initiated;
+						d.disputed = disputed;
+						d.shortfall = shortfall;
+						self.shortfall += shortfall;
+						self.available = available;
+						self.held = held;
+					} else if d.dispute_state == DisputeState::Initiated {
+						return Err(RejectReason::DuplicateDispute);
+					}
+				}
+				Ok(())
+			}
+			Transaction::Resolve { tx, amount } if self.can_dispute(conf) => {
+				match self.deposits.get_mut(&tx) {
+					Some(d) => match d.dispute_state {
+						DisputeState::Initiated => {
+							// Partial amounts take the sign of the deposit
+							// like with partial chargebacks
+							let sign = d.amount.signum();
+							if let Some(a) = amount {
+								let a = checked_minor(conf, a)? * sign;
+								if a * sign <= 0 {
+									return Err(RejectReason::InvalidAmount);
+								}
+								if a * sign > d.disputed * sign {
+									return Err(
+										RejectReason::AmountExceedsDisputed,
+									);
+								}
+								if a != d.disputed {
+									let (available, held) = release_held(
+										conf,
+										(self.available, self.held),
+										d.held_share(a),
+										annotate,
+										&mut clamped,
+									)?;
+									self.shortfall -= d.settle_shortfall(a);
+									self.available = available;
+									self.held = held;
+									d.disputed -= a;
+									d.released += a;
+									return Ok(());
+								}
+							}
+
+							// Everything not charged back or released is held
+							// under the dispute, so releasing it must bring
+							// the deposit's share of the held funds back to
+							// zero. Anything else is an error in the balance
+							// arithmetic or a corrupted restored state, that
+							// would accumulate over repeated disputes.
+							if !d.is_consistent() {
+								return Err(RejectReason::InconsistentDeposit);
+							}
+							let (available, held) = release_held(
+								conf,
+								(self.available, self.held),
+								d.held_share(d.disputed),
+								annotate,
+								&mut clamped,
+							)?;
+							self.shortfall -= d.settle_shortfall(d.disputed);
+							self.available = available;
+							self.held = held;
+							d.disputed = 0;
+							d.released = 0;
+
+							// Enable starting another dispute
+							d.dispute_state = DisputeState::NotInitiated;
+							Ok(())
+						}
+						// Indicates upstream confusion about the dispute state
+						DisputeState::ChargedBack => {
+							Err(RejectReason::ResolveAfterChargeback)
+						}
+						DisputeState::NotInitiated
+							if conf.reject_undisputed_resolves
+								|| conf.is_strict() =>
+						{
+							Err(RejectReason::ResolveWithoutDispute)
+						}
+						DisputeState::NotInitiated => Ok(()),
+					},
+					None => Ok(()),
+				}
+			}
+			Transaction::Chargeback { tx, amount }
+				if self.can_dispute(conf) =>
+			{
+				if conf.chargeback_opens_dispute
+					&& self.deposits.get(&tx).is_some_and(|d| {
+						d.dispute_state == DisputeState::NotInitiated
+					}) {
+					// Counted as part of the chargeback
+					let counted = (self.applied, self.last_tx_type);
+					self.apply_with(&Transaction::Dispute { tx }, conf)?;
+					(self.applied, self.last_tx_type) = counted;
+				}
+
+				match self.deposits.get_mut(&tx) {
+					Some(d)
+						if matches!(
+							d.dispute_state,
+							DisputeState::Initiated
+						) =>
+					{
+						// Charge back the entire disputed amount, unless
+						// a partial amount is specified.
+						// Amounts of recorded withdrawals are negative, so
+						// partial amounts take their sign and are compared
+						// by magnitude.
+						let sign = d.amount.signum();
+						let amount = match amount {
+							Some(a) => checked_minor(conf, a)? * sign,
+							None => d.disputed,
+						};
+						if amount * sign <= 0 {
+							Err(RejectReason::InvalidAmount)
+						} else if amount * sign > d.disputed * sign {
+							Err(RejectReason::AmountExceedsDisputed)
+						} else {
+							// Balances are computed before modifying any
+							// state, so an overflow leaves the account as is.
+							// Shortfall stays owed by the client. Charged
+							// back withdrawals are returned to the available
+							// funds.
+							let owed = d.owed(amount);
+							let held = clamp_overflow(
+								conf,
+								self.held.checked_sub(d.held_share(amount)),
+								self.held.saturating_sub(d.held_share(amount)),
+								&mut clamped,
+							)?;
+							let available = if annotate || amount < 0 {
+								clamp_overflow(
+									conf,
+									self.available.checked_sub(amount),
+									self.available.saturating_sub(amount),
+									&mut clamped,
+								)?
+							} else {
+								self.available
+							};
+							let leftover = d.disputed - amount;
+							let release = leftover == 0
+								|| conf.partial_chargeback
+									== PartialChargeback::Resolve;
+							let (available, held) = if release {
+								let owed =
+									leftover.clamp(0, d.shortfall - owed);
+								release_held(
+									conf,
+									(available, held),
+									if d.amount < 0 {
+										0
+									} else {
+										leftover - owed
+									},
+									annotate,
+									&mut clamped,
+								)?
+							} else {
+								(available, held)
+							};
+
+							d.settle_shortfall(amount);
+							d.disputed -= amount;
+							d.charged_back += amount;
+							self.available = available;
+							self.held = held;
+							if !self.locked && !conf.no_lock_on_chargeback {
+								self.locked = true;
+								self.locked_reason = Some(tx);
+							}
+
+							if release {
+								self.shortfall -=
+									d.settle_shortfall(d.disputed);
+								d.disputed = 0;
+								d.dispute_state = DisputeState::ChargedBack;
+								if conf.charged_back
+									== ChargedBackDeposits::Prune
+								{
+									self.deposits.remove(&tx);
+								}
+							}
+							Ok(())
+						}
+					}
+					_ => Ok(()),
+				}
+			}
+			Transaction::Adjustment { .. } if !conf.allow_admin => {
+				Err(RejectReason::AdminNotAllowed)
+			}
+			Transaction::Adjustment { amount, .. } => {
+				let amount = checked_minor(conf, amount)?;
+				let available = clamp_overflow(
+					conf,
+					self.available.checked_add(amount),
+					self.available.saturating_add(amount),
+					&mut clamped,
+				)?;
+				if amount < 0 && available < 0 {
+					return Err(RejectReason::InsufficientFunds);
+				}
+				self.available = available;
+				Ok(())
+			}
+			Transaction::Unknown(ref tx) => match &conf.unknown_tx {
+				UnknownTxPolicy::Reject => Err(RejectReason::UnknownType),
+				UnknownTxPolicy::Handler(h) => h(tx, self),
+				UnknownTxPolicy::Ignore | UnknownTxPolicy::Fail => Ok(()),
+			},
+			// Ignoring invalid cases to match behaviour of all other
+			// validations
+			_ => Ok(()),
+		};
+		if (res.is_ok() || clamped) && effect(self) != before {
+			self.applied += 1;
+			self.last_tx_type = Some(tx.typ());
+		}
+		if clamped {
+			res.and(Err(RejectReason::Overflow))
+		} else {
+			res
+		}
+	}
+}
+
+/// Available and held balances after releasing `amount` of the held funds.
+/// The released funds are not added to the available ones with `annotate`,
+/// as those were never deducted.
+fn release_held(
+	conf: &Config,
+	(available, held): (i64, i64),
+	amount: i64,
+	annotate: bool,
+	clamped: &mut bool,
+) -> Result<(i64, i64), RejectReason> {
+	let held = clamp_overflow(
+		conf,
+		held.checked_sub(amount),
+		held.saturating_sub(amount),
+		clamped,
+	)?;
+	let available = if annotate {
+		available
+	} else {
+		clamp_overflow(
+			conf,
+			available.checked_add(amount),
+			available.saturating_add(amount),
+			clamped,
+		)?
+	};
+	Ok((available, held))
+}
+
+/// Result of checked balance arithmetic `checked` or, with
+/// [OverflowPolicy::ClampAndReport], the `saturated` result on overflow, which
+/// sets `clamped`
+fn clamp_overflow(
+	conf: &Config,
+	checked: Option<i64>,
+	saturated: i64,
+	clamped: &mut bool,
+) -> Result<i64, RejectReason> {
+	match checked {
+		Some(v) => Ok(v),
+		None if conf.on_overflow == OverflowPolicy::ClampAndReport => {
+			*clamped = true;
+			Ok(saturated)
+		}
+		None => Err(RejectReason::Overflow),
+	}
+}
+
+/// Account of `client`, created with the next [Account::first_seen] sequence
+/// number and [Config::opening_balance], if not present
+fn account_entry<'a>(
+	accounts: &'a mut HashMap<u16, Account>,
+	client: u16,
+	conf: &Config,
+) -> &'a mut Account {
+	let first_seen = accounts.len() as u64;
+	accounts.entry(client).or_insert_with(|| Account {
+		first_seen,
+		available: conf.opening_balance.map_or(0, |b| to_minor(conf, b)),
+		..Default::default()
+	})
+}
+
+/// Combine account maps produced by processing separate shards of the input.
+///
+/// Clients present in only one of the maps are moved over as is. Balances of
+/// clients present in both are summed and their account is locked, if either
+/// side is locked.
+///
+/// Transaction IDs are globally unique, so a deposit present in both
+/// registries means the shards overlapped. The record from `a` is kept along
+/// with its dispute state and the one from `b` is dropped. Balances still
+/// include both, as they were already applied in their respective shards.
+pub fn merge_accounts(
+	mut a: HashMap<u16, Account>,
+	b: HashMap<u16, Account>,
+) -> HashMap<u16, Account> {
+	for (cl, acc) in b {
+		match a.entry(cl) {
+			Entry::Occupied(mut e) => e.get_mut().merge(acc),
+			Entry::Vacant(e) => {
+				e.insert(acc);
+			}
+		}
+	}
+	a
+}
+
+/// Minor currency units in each major unit set by [Config::units_per_major]
+/// or [Config::scale]
+fn units_per_major(conf: &Config) -> i64 {
+	match conf.scale {
+		Some(s) => 10_i64.pow(s.min(9)),
+		None => conf.units_per_major.map_or(10_000, |u| u.get().into()),
+	}
+}
+
+/// Decimal places of major unit output set by [Config::scale]
+fn output_scale(conf: &Config) -> u32 {
+	conf.scale.map_or(4, |s| s.min(9))
+}
+
+/// Convert amount in major currency units to minor units.
+///
+/// Done to avoid FP arithmetic errors.
+/// There are 10_000 minor in each major unit of currency, unless configured
+/// otherwise with [Config::units_per_major].
+///
+/// If arbitrary precisions is desired, these can be switched to bignums later
+/// on. Not used at the moment, as ints are more efficient.
+fn to_minor(conf: &Config, amount: f64) -> i64 {
+	// Rounded, as most decimal fractions are not exactly representable and
+	// truncation would lose a minor unit on values like 0.0003
+	(amount * units_per_major(conf) as f64).round() as _
+}
+
+/// Convert amount in major currency units to minor units, if it is a finite
+/// number within the range of minor unit amounts
+fn checked_minor(conf: &Config, amount: f64) -> Result<i64, RejectReason> {
+	let max = (i64::MAX / units_per_major(conf)) as f64;
+	if amount.is_finite() && amount.abs() <= max {
+		Ok(to_minor(conf, amount))
+	} else {
+		Err(RejectReason::InvalidAmount)
+	}
+}
+
+/// Comment line describing the amount scale of the output for
+/// [Config::scale_header]
+fn scale_header(conf: &Config) -> String {
+	format!(
+		"# scale={} units_per_major={}",
+		if conf.minor_units {
+			0
+		} else {
+			output_scale(conf)
+		},
+		units_per_major(conf)
+	)
+}
+
+/// Format amount in minor currency units for output as either major units or
+/// with [Config::minor_units] unconverted minor units
+fn format_amount(conf: &Config, amount: impl Into<i128>) -> String {
+	let amount = amount.into();
+	if conf.minor_units {
+		amount.to_string()
+	} else {
+		let s = to_major(conf, amount);
+		match conf.decimal_separator {
+			Some(sep) if sep != '.' => {
+				s.replace('.', sep.encode_utf8(&mut [0; 4]))
+			}
+			_ => s,
+		}
+	}
+}
+
+/// Convert amount in minor currency units to a major unit string of 4 decimal
+/// or [Config::scale] precision.
+///
+/// Computed on integers, so large amounts are not subject to floating point
+/// precision loss. Amounts more precise than 4 decimals, due to
+/// [Config::units_per_major], are rounded half to even.
+fn to_major(conf: &Config, amount: i128) -> String {
+	let units = units_per_major(conf) as u128;
+	let decimals = output_scale(conf);
+	let pow = 10_u128.pow(decimals);
+	let scaled = amount.unsigned_abs() * pow;
+	let (mut q, r) = (scaled / units, scaled % units);
+	if 2 * r > units || (2 * r == units && q % 2 == 1) {
+		q += 1;
+	}
+	let sign = if amount < 0 && q != 0 { "-" } else { "" };
+	if decimals == 0 {
+		format!("{}{}", sign, q)
+	} else {
+		format!("{}{}.{:0w$}", sign, q / pow, q % pow, w = decimals as usize)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use std::{
+		collections::HashMap,
+		io::{self, Cursor},
+	};
+
+	use super::*;
+
+	/// Load input sample and expected output
+	macro_rules! load_samples {
+		($dir:literal) => {{
+			(
+				include_str!(concat!("../test_samples/", $dir, "/in.csv")),
+				include_str!(concat!("../test_samples/", $dir, "/out.csv")),
+			)
+		}};
+	}
+
+	// Simple case of deposits and withdrawals
+	#[test]
+	fn simple() {
+		let (input, expected) = load_samples!("simple");
+		compare(input, expected);
+	}
+
+	// Deposits, withdrawals and dispute resolution
+	#[test]
+	fn disputes() {
+		let (input, expected) = load_samples!("disputes");
+		compare(input, expected);
+	}
+
+	// Resolving a charged back deposit is rejected
+	#[test]
+	fn resolve_after_chargeback() {
+		const INPUT: &str = "type, client, tx, amount
+			deposit, 1, 1, 1.0
+			deposit, 1, 2, 2.0
+			dispute, 1, 1,
+			chargeback, 1, 1,
+			resolve, 1, 1,";
+		let expected = Rejection {
+			line: 6,
+			typ: TxType::Resolve,
+			client: 1,
+			tx: 1,
+			reason: RejectReason::ResolveAfterChargeback,
+		};
+		assert_rejected(INPUT, Default::default(), expected);
+	}
+
+	// Disputes of least recently used deposits evicted from the registry are
+	// rejected
+	#[test]
+	fn deposit_cache() {
+		let mut accounts = HashMap::new();
+		let report = read_accounts(
+			&mut accounts,
+			&mut Cursor::new(
+				"type, client, tx, amount
+				deposit, 1, 1, 1.0
+				deposit, 2, 2, 2.0
+				dispute, 1, 1,
+				deposit, 1, 3, 3.0
+				deposit, 2, 4, 4.0
+				dispute, 2, 2,",
+			),
+			&Config {
+				deposit_cache: NonZeroUsize::new(2),
+				..Default::default()
+			},
+		)
+		.unwrap();
+		assert_eq!(
+			report.rejections,
+			[Rejection {
+				line: 7,
+				typ: TxType::Dispute,
+				client: 2,
+				tx: 2,
+				reason: RejectReason::DepositEvicted,
+			}]
+		);
+
+		// Disputed deposit kept beyond the capacity
+		let mut txs = accounts
+			.values()
+			.flat_map(|acc| acc.deposits.keys().copied())
+			.collect::<Vec<_>>();
+		txs.sort_unstable();
+		assert_eq!(txs, [1, 4]);
+
+		// Only as many evicted deposits as the capacity are remembered
+		let report = read_accounts(
+			&mut HashMap::new(),
+			&mut Cursor::new(
+				"type, client, tx, amount
+				deposit, 1, 1, 1.0
+				deposit, 1, 2, 2.0
+				deposit, 1, 3, 3.0
+				dispute, 1, 1,
+				dispute, 1, 2,",
+			),
+			&Config {
+				deposit_cache: NonZeroUsize::new(1),
+				..Default::default()
+			},
+		)
+		.unwrap();
+		assert_eq!(
+			report.rejections,
+			[Rejection {
+				line: 6,
+				typ: TxType::Dispute,
+				client: 1,
+				tx: 2,
+				reason: RejectReason::DepositEvicted,
+			}]
+		);
+	}
+
+	// Using a deposit again makes it the most recently used one
+	#[test]
+	fn deposit_cache_recency() {
+		let mut cache = DepositCache::new(2);
+		cache.touch(1, 1);
+		cache.touch(1, 2);
+		assert_eq!(cache.pop_excess(0), None);
+		cache.touch(1, 1);
+		cache.touch(2, 3);
+		assert_eq!(cache.pop_excess(0), Some((1, 2)));
+		assert_eq!(cache.pop_excess(0), None);
+
+		// Deposits held aside by the caller count towards the capacity
+		assert_eq!(cache.pop_excess(1), Some((1, 1)));
+		assert_eq!(cache.pop_excess(1), None);
+	}
+
+	// Capacity 0 tracks and remembers nothing
+	#[test]
+	fn deposit_cache_capacity_0() {
+		let mut cache = DepositCache::new(0);
+		cache.touch(1, 1);
+		assert_eq!(cache.pop_excess(0), Some((1, 1)));
+		assert_eq!(cache.pop_excess(0), None);
+		cache.record_eviction(1, 1);
+		assert!(!cache.was_evicted(1, 1));
+	}
+
+	// Capacity 1 keeps only the latest used and the latest evicted deposit
+	#[test]
+	fn deposit_cache_capacity_1() {
+		let mut cache = DepositCache::new(1);
+		cache.touch(1, 1);
+		assert_eq!(cache.pop_excess(0), None);
+		cache.touch(1, 2);
+		cache.touch(1, 2);
+		assert_eq!(cache.pop_excess(0), Some((1, 1)));
+		assert_eq!(cache.pop_excess(0), None);
+
+		cache.record_eviction(1, 1);
+		assert!(cache.was_evicted(1, 1));
+		cache.record_eviction(2, 1);
+		assert!(!cache.was_evicted(1, 1));
+		assert!(cache.was_evicted(2, 1));
+	}
+
+	// Disputes of deposits that fell out of the dispute window are rejected
+	#[test]
+	fn dispute_window() {
+		let mut accounts = HashMap::new();
+		let report = read_accounts(
+			&mut accounts,
+			&mut Cursor::new(
+				"type, client, tx, amount
+				deposit, 1, 1, 1.0
+				deposit, 2, 2, 2.0
+				deposit, 1, 3, 3.0
+				deposit, 2, 4, 4.0
+				dispute, 1, 1,
+				dispute, 1, 3,",
+			),
+			&Config {
+				dispute_window: Some(2),
+				..Default::default()
+			},
+		)
+		.unwrap();
+
+		assert_eq!(
+			report.rejections,
+			vec![Rejection {
+				line: 6,
+				typ: TxType::Dispute,
+				client: 1,
+				tx: 1,
+				reason: RejectReason::DisputeWindowExpired,
+			}]
+		);
+		assert_eq!(balances(&accounts[&1]), (10_000, 30_000, false));
+		assert!(!accounts[&1].deposits.contains_key(&1));
+	}
+
+	// JSON object output keyed by client ID
+	#[test]
+	fn json_map() {
+		let (input, _) = load_samples!("disputes");
+		let mut res = vec![];
+		process(
+			&mut Cursor::new(&mut res),
+			&mut Cursor::new(input),
+			&Config {
+				output_format: OutputFormat::JsonMap,
+				..Default::default()
+			},
+		)
+		.unwrap();
+
+		let v: serde_json::Value = serde_json::from_slice(&res).unwrap();
+		let clients = v.as_object().unwrap();
+		assert_eq!(clients.keys().collect::<Vec<_>>(), ["1", "2"]);
+		assert_eq!(
+			clients["2"],
+			serde_json::json!({
+				"client": 2,
+				"available": "5.0000",
+				"held": "0.0000",
+				"total": "5.0000",
+				"locked": true,
+			})
+		);
+	}
+
+	// YAML mapping keyed by client ID
+	#[cfg(feature = "yaml")]
+	#[test]
+	fn yaml() {
+		let (input, _) = load_samples!("disputes");
+		let mut res = vec![];
+		process(
+			&mut Cursor::new(&mut res),
+			&mut Cursor::new(input),
+			&Config {
+				output_format: OutputFormat::Yaml,
+				..Default::default()
+			},
+		)
+		.unwrap();
+
+		let res = std::str::from_utf8(&res).unwrap();
+		let (_, client_2) = res.split_once("2:\n").unwrap();
+		let fields = client_2
+			.lines()
+			.map(|l| {
+				let (k, v) = l.trim().split_once(": ").unwrap();
+				(k, serde_json::from_str(v).unwrap())
+			})
+			.collect::<Vec<(_, serde_json::Value)>>();
+		assert_eq!(
+			fields,
+			[
+				("client", 2.into()),
+				("available", "5.0000".into()),
+				("held", "0.0000".into()),
+				("total", "5.0000".into()),
+				("locked", true.into()),
+			]
+		);
+	}
+
+	// Withdrawable funds of unlocked, locked and overdrawn accounts
+	#[test]
+	fn withdrawable() {
+		let conf = Config::default();
+		let acc = |available, locked| Account {
+			available,
+			locked,
+			..Default::default()
+		};
+		assert_eq!(acc(15_000, false).withdrawable(&conf), 15_000);
+		assert_eq!(acc(15_000, true).withdrawable(&conf), 0);
+		assert_eq!(acc(-5_000, false).withdrawable(&conf), 0);
+
+		// Withdrawals are not blocked by the lock flag
+		let conf = Config {
+			flag_policy: FlagPolicy {
+				locked: Blocked::default(),
+				..Default::default()
+			},
+			..Default::default()
+		};
+		assert_eq!(acc(15_000, true).withdrawable(&conf), 15_000);
+	}
+
+	// Withdrawal gating by the locked and frozen flags
+	#[test]
+	fn can_withdraw() {
+		let mut conf = Config::default();
+		let check = |conf: &Config, locked, frozen| {
+			Account {
+				locked,
+				frozen,
+				..Default::default()
+			}
+			.can_withdraw(conf)
+		};
+
+		assert!(check(&conf, false, false));
+		assert!(!check(&conf, true, false));
+		assert!(!check(&conf, false, true));
+		assert!(!check(&conf, true, true));
+
+		conf.flag_policy.locked = Blocked::default();
+		assert!(check(&conf, false, false));
+		assert!(check(&conf, true, false));
+		assert!(!check(&conf, false, true));
+		assert!(!check(&conf, true, true));
+	}
+
+	// Rows that fail to parse are collected instead of aborting processing
+	#[test]
+	fn collect_errors() {
+		const INPUT: &str = "type, client, tx, amount
+			deposit, 1, 1, 1.0
+			deposit, x, 2, 2.0
+			deposit, 2, 3, 3.0
+			refund, 1, 4, 1.0
+			withdrawal, 1, 5, 0.5";
+
+		assert!(matches!(
+			process(
+				&mut io::sink(),
+				&mut Cursor::new(INPUT),
+				&Default::default()
+			),
+			Err(ProcessError::Csv(_))
+		));
+
+		let mut res = vec![];
+		let report = process(
+			&mut Cursor::new(&mut res),
+			&mut Cursor::new(INPUT),
+			&Config {
+				collect_errors: true,
+				..Default::default()
+			},
+		)
+		.unwrap();
+		assert_eq!(
+			report
+				.parse_errors
+				.iter()
+				.map(|e| e.line)
+				.collect::<Vec<_>>(),
+			[3, 5]
+		);
+		compare_output(
+			&res,
+			"client,available,held,total,locked
+			1,0.5000,0.0000,0.5000,false
+			2,3.0000,0.0000,3.0000,false",
+		);
+	}
+
+	// Chargeback of part of the disputed amount
+	#[test]
+	fn partial_chargeback() {
+		const INPUT: &str = "type, client, tx, amount
+			deposit, 1, 1, 10.0
+			deposit, 1, 2, 1.0
+			dispute, 1, 1,
+			chargeback, 1, 1, 4.0
+			chargeback, 1, 1, 7.0";
+		let run = |partial_chargeback| {
+			let mut accounts = HashMap::new();
+			let report = read_accounts(
+				&mut accounts,
+				&mut Cursor::new(INPUT),
+				&Config {
+					partial_chargeback,
+					..Default::default()
+				},
+			)
+			.unwrap();
+			(accounts.remove(&1).unwrap(), report)
+		};
+
+		// Leftover stays held and charging back more than it is rejected
+		let (acc, report) = run(PartialChargeback::StayHeld);
+		assert_eq!(balances(&acc), (10_000, 60_000, true));
+		assert_eq!(acc.deposits[&1].dispute_state, DisputeState::Initiated);
+		assert_eq!(
+			report
+				.rejections
+				.iter()
+				.map(|r| (r.line, r.reason))
+				.collect::<Vec<_>>(),
+			[(6, RejectReason::AmountExceedsDisputed)]
+		);
+
+		// Leftover is released and the dispute ends
+		let (acc, report) = run(PartialChargeback::Resolve);
+		assert_eq!(balances(&acc), (70_000, 0, true));
+		assert_eq!(acc.deposits[&1].dispute_state, DisputeState::ChargedBack);
+		assert!(report.rejections.is_empty());
+	}
+
+	// Locking chargeback transaction is recorded and written in extended
+	// output
+	#[test]
+	fn locked_reason() {
+		const INPUT: &str = "type, client, tx, amount
+			deposit, 1, 1, 1.0
+			deposit, 1, 2, 2.0
+			deposit, 2, 3, 3.0
+			dispute, 1, 2,
+			chargeback, 1, 2,
+			dispute, 1, 1,
+			chargeback, 1, 1,";
+		let conf = Config {
+			extended_output: true,
+			..Default::default()
+		};
+
+		let mut accounts = HashMap::new();
+		read_accounts(&mut accounts, &mut Cursor::new(INPUT), &conf).unwrap();
+		assert_eq!(accounts[&1].locked_reason, Some(2));
+		assert_eq!(accounts[&2].locked_reason, None);
+
+		compare_with(
+			INPUT,
+			"client,available,held,total,locked,locked_reason,last_tx_type,\
+			tx_count,rows_seen
+			1,0.0000,0.0000,0.0000,true,2,chargeback,6,6
+			2,3.0000,0.0000,3.0000,false,,deposit,1,1",
+			&conf,
+		);
+	}
+
+	// Rejected and ignored rows are seen, but not applied
+	#[test]
+	fn rows_seen() {
+		let mut accounts = HashMap::new();
+		read_accounts(
+			&mut accounts,
+			&mut Cursor::new(
+				"type, client, tx, amount
+				deposit, 1, 1, 1.0
+				withdrawal, 1, 2, 5.0
+				dispute, 1, 3,
+				deposit, 1, 4,
+				deposit, 2, 5, 1.0",
+			),
+			&Config::default(),
+		)
+		.unwrap();
+		assert_eq!((accounts[&1].applied, accounts[&1].rows_seen), (1, 4));
+		assert_eq!((accounts[&2].applied, accounts[&2].rows_seen), (1, 1));
+	}
+
+	// Type of the latest applied transaction in the extended output
+	#[test]
+	fn last_tx_type() {
+		const INPUT: &str = "type, client, tx, amount
+			deposit, 1, 1, 2.0
+			withdrawal, 1, 2, 1.0
+			withdrawal, 1, 3, 5.0";
+		let mut accounts = HashMap::new();
+		read_accounts(
+			&mut accounts,
+			&mut Cursor::new(INPUT),
+			&Config::default(),
+		)
+		.unwrap();
+		assert_eq!(accounts[&1].last_tx_type, Some(TxType::Withdrawal));
+
+		// The insufficient funds withdrawal is seen, but not applied
+		compare_with(
+			INPUT,
+			"client,available,held,total,locked,locked_reason,last_tx_type,\
+			tx_count,rows_seen
+			1,1.0000,0.0000,1.0000,false,,withdrawal,2,3",
+			&Config {
+				extended_output: true,
+				..Default::default()
+			},
+		);
+	}
+
+	// Binary state snapshot round trip
+	#[test]
+	fn bincode_state() {
+		let (input, _) = load_samples!("disputes");
+		let read = || {
+			let mut accounts = HashMap::new();
+			read_accounts(
+				&mut accounts,
+				&mut Cursor::new(input),
+				&Default::default(),
+			)
+			.unwrap();
+			accounts
+		};
+
+		let mut snapshot = vec![];
+		write_accounts(
+			&mut snapshot,
+			read(),
+			&Config {
+				output_format: OutputFormat::Bincode,
+				..Default::default()
+			},
+		)
+		.unwrap();
+		assert_eq!(load_state(&mut snapshot.as_slice()).unwrap(), read());
+	}
+
+	// Restored accounts with held funds not matching open disputes
+	#[test]
+	fn state_check() {
+		let (input, _) = load_samples!("disputes");
+		let mut accounts = HashMap::new();
+		read_accounts(
+			&mut accounts,
+			&mut Cursor::new(input),
+			&Default::default(),
+		)
+		.unwrap();
+		accounts.get_mut(&1).unwrap().held += 5_000;
+
+		let mut snapshot = vec![];
+		write_accounts(
+			&mut snapshot,
+			accounts,
+			&Config {
+				output_format: OutputFormat::Bincode,
+				..Default::default()
+			},
+		)
+		.unwrap();
+		let load = |state_check| {
+			load_state_with(
+				&mut snapshot.as_slice(),
+				&Config {
+					state_check,
+					..Default::default()
+				},
+			)
+		};
+
+		assert_eq!(load(StateCheck::Ignore).unwrap()[&1].held, 15_000);
+		let acc = &load(StateCheck::Correct).unwrap()[&1];
+		assert_eq!(balances(acc), (5_000, 10_000, false));
+		assert_eq!(acc.total(&Default::default()), 15_000);
+		assert!(matches!(
+			load(StateCheck::Fail),
+			Err(ProcessError::InconsistentState(1))
+		));
+	}
+
+	// Restored open dispute with amounts not adding up to its deposit
+	#[test]
+	fn state_check_deposit() {
+		let mut acc = Account {
+			available: 10_000,
+			..Default::default()
+		};
+		acc.deposits.insert(
+			1,
+			Deposit {
+				dispute_state: DisputeState::Initiated,
+				..Deposit::new(10_000)
+			},
+		);
+		assert_eq!(
+			acc.clone().apply(&Transaction::Resolve {
+				tx: 1,
+				amount: None
+			}),
+			Err(RejectReason::InconsistentDeposit)
+		);
+
+		let mut snapshot = vec![];
+		write_accounts(
+			&mut snapshot,
+			[(1, acc)].into(),
+			&Config {
+				output_format: OutputFormat::Bincode,
+				..Default::default()
+			},
+		)
+		.unwrap();
+		let load = |state_check| {
+			load_state_with(
+				&mut snapshot.as_slice(),
+				&Config {
+					state_check,
+					..Default::default()
+				},
+			)
+		};
+
+		assert!(matches!(
+			load(StateCheck::Fail),
+			Err(ProcessError::InconsistentState(1))
+		));
+		let acc = &load(StateCheck::Correct).unwrap()[&1];
+		assert_eq!(acc.deposits[&1].disputed, 10_000);
+		assert_eq!(balances(acc), (10_000, 10_000, false));
+	}
+
+	// Processing a CSV file in a zip archive
+	#[test]
+	fn zip_entry() {
+		use zip::write::{SimpleFileOptions, ZipWriter};
+
+		let (input, expected) = load_samples!("disputes");
+		let archive = |files: &[&str]| {
+			let mut w = ZipWriter::new(Cursor::new(vec![]));
+			for name in files {
+				w.start_file(*name, SimpleFileOptions::default()).unwrap();
+				w.write_all(input.as_bytes()).unwrap();
+			}
+			w.finish().unwrap()
+		};
+		let run = |files: &[&str], entry| {
+			let mut accounts = HashMap::new();
+			read_zip_accounts(
+				&mut accounts,
+				archive(files),
+				entry,
+				&Default::default(),
+			)?;
+			let mut res = vec![];
+			write_accounts(&mut res, accounts, &Default::default())?;
+			Ok::<_, ProcessError>(res)
+		};
+
+		compare_output(
+			&run(&["a.csv", "b.csv"], Some("b.csv")).unwrap(),
+			expected,
+		);
+		compare_output(&run(&["a.csv"], None).unwrap(), expected);
+		assert!(matches!(
+			run(&["a.csv", "b.csv"], None),
+			Err(ProcessError::ZipEntryRequired)
+		));
+		assert!(matches!(
+			run(&["a.csv"], Some("c.csv")),
+			Err(ProcessError::Zip(_))
+		));
+	}
+
+	// Currency label column
+	#[test]
+	fn currency() {
+		let (input, _) = load_samples!("simple");
+		compare_with(
+			input,
+			"client,available,held,total,locked,currency
+			1,1.5000,0.0000,1.5000,false,USD
+			2,2.0000,0.0000,2.0000,false,USD",
+			&Config {
+				currency: Some("USD".into()),
+				..Default::default()
+			},
+		);
+	}
+
+	// Deposit with an empty amount field is rejected
+	#[test]
+	fn missing_amount() {
+		const INPUT: &str = "type, client, tx, amount
+			deposit, 1, 1, 1.0
+			deposit, 1, 2,";
+		let expected = Rejection {
+			line: 3,
+			typ: TxType::Deposit,
+			client: 1,
+			tx: 2,
+			reason: RejectReason::MissingAmount,
+		};
+		assert_rejected(INPUT, Default::default(), expected);
+	}
+
+	// Deposit with a whitespace-only amount field is rejected like an empty one
+	#[test]
+	fn whitespace_amount() {
+		for amount in ["   ", "\t \t", "\"  \""] {
+			assert_rejected(
+				&format!(
+					"type, client, tx, amount
+					deposit, 1, 1, 1.0
+					deposit, 1, 2,{}",
+					amount
+				),
+				Default::default(),
+				Rejection {
+					line: 3,
+					typ: TxType::Deposit,
+					client: 1,
+					tx: 2,
+					reason: RejectReason::MissingAmount,
+				},
+			);
+		}
+	}
+
+	// Decreasing transaction IDs of a client are rejected
+	#[test]
+	fn out_of_order() {
+		assert_rejected(
+			"type, client, tx, amount
+			deposit, 1, 5, 1.0
+			deposit, 2, 4, 1.0
+			deposit, 1, 3, 2.0",
+			Config {
+				verify_tx_order: true,
+				..Default::default()
+			},
+			Rejection {
+				line: 4,
+				typ: TxType::Deposit,
+				client: 1,
+				tx: 3,
+				reason: RejectReason::OutOfOrder,
+			},
+		);
+	}
+
+	// Account statuses exported as JSON values
+	#[test]
+	fn to_json() {
+		let mut accounts = HashMap::new();
+		read_accounts(
+			&mut accounts,
+			&mut Cursor::new(
+				"type, client, tx, amount
+				deposit, 1, 1, 1.5
+				deposit, 2, 2, 2.0
+				dispute, 2, 2,",
+			),
+			&Default::default(),
+		)
+		.unwrap();
+
+		assert_eq!(
+			accounts[&1].to_json(),
+			serde_json::json!({
+				"available": "1.5000",
+				"held": "0.0000",
+				"total": "1.5000",
+				"locked": false,
+			})
+		);
+		assert_eq!(
+			accounts_to_json(&accounts),
+			serde_json::json!([
+				{
+					"client": 1,
+					"available": "1.5000",
+					"held": "0.0000",
+					"total": "1.5000",
+					"locked": false,
+				},
+				{
+					"client": 2,
+					"available": "0.0000",
+					"held": "2.0000",
+					"total": "2.0000",
+					"locked": false,
+				},
+			])
+		);
+	}
+
+	// Chargeback of a never disputed deposit opens a dispute first
+	#[test]
+	fn chargeback_opens_dispute() {
+		const INPUT: &str = "type, client, tx, amount
+			deposit, 1, 1, 1.0
+			deposit, 1, 2, 2.0
+			chargeback, 1, 1, 0.4";
+
+		compare_with(
+			INPUT,
+			"client,available,held,total,locked
+			1,3.0000,0.0000,3.0000,false",
+			&Default::default(),
+		);
+		compare_with(
+			INPUT,
+			"client,available,held,total,locked
+			1,2.0000,0.6000,2.6000,true",
+			&Config {
+				chargeback_opens_dispute: true,
+				..Default::default()
+			},
+		);
+
+		// Opening the dispute is not counted as a separate transaction
+		let mut accounts = HashMap::new();
+		read_accounts(
+			&mut accounts,
+			&mut Cursor::new(INPUT),
+			&Config {
+				chargeback_opens_dispute: true,
+				..Default::default()
+			},
+		)
+		.unwrap();
+		assert_eq!((accounts[&1].applied, accounts[&1].rows_seen), (3, 3));
+		assert_eq!(accounts[&1].last_tx_type, Some(TxType::Chargeback));
+	}
+
+	// Rejections written as CSV
+	#[test]
+	fn rejection_csv() {
+		let report = process(
+			&mut io::sink(),
+			&mut Cursor::new(
+				"type, client, tx, amount
+				deposit, 1, 1, 1.0
+				deposit, 1, 2,
+				dispute, 1, 1,
+				dispute, 1, 1,
+				chargeback, 1, 1, 5.0
+				deposit, 2, 3, -1e300",
+			),
+			&Default::default(),
+		)
+		.unwrap();
+
+		let mut res = vec![];
+		write_rejections(&mut res, &report.rejections).unwrap();
+		assert_eq!(
+			std::str::from_utf8(&res).unwrap(),
+			"line,client,tx,type,reason
+3,1,2,deposit,missing_amount
+5,1,1,dispute,duplicate_dispute
+6,1,1,chargeback,amount_exceeds_disputed
+7,2,3,deposit,invalid_amount
+"
+		);
+	}
+
+	// Resolve releasing only part of the disputed funds
+	#[test]
+	fn partial_resolve() {
+		let mut accounts = HashMap::new();
+		read_accounts(
+			&mut accounts,
+			&mut Cursor::new(
+				"type, client, tx, amount
+				deposit, 1, 1, 3.0
+				dispute, 1, 1,
+				resolve, 1, 1, 1.0
+				resolve, 1, 1, 2.5",
+			),
+			&Default::default(),
+		)
+		.unwrap();
+		let acc = &accounts[&1];
+		assert_eq!(balances(acc), (10_000, 20_000, false));
+		assert_eq!(acc.deposits[&1].disputed, 20_000);
+		assert_eq!(acc.deposits[&1].released, 10_000);
+		assert_eq!(acc.deposits[&1].dispute_state, DisputeState::Initiated);
+
+		let mut acc = accounts.remove(&1).unwrap();
+		assert_eq!(
+			acc.apply(&Transaction::Resolve {
+				tx: 1,
+				amount: Some(2.5),
+			}),
+			Err(RejectReason::AmountExceedsDisputed)
+		);
+		acc.apply(&Transaction::Resolve {
+			tx: 1,
+			amount: Some(2.0),
+		})
+		.unwrap();
+		assert_eq!(balances(&acc), (30_000, 0, false));
+		assert_eq!(acc.deposits[&1].released, 0);
+		assert_eq!(acc.deposits[&1].dispute_state, DisputeState::NotInitiated);
+	}
+
+	// New accounts are opened with the configured balance
+	#[test]
+	fn opening_balance() {
+		compare_with(
+			"type, client, tx, amount
+			withdrawal, 1, 1, 3.0
+			deposit, 2, 2, 1.0
+			withdrawal, 2, 3, 6.5",
+			"client,available,held,total,locked
+			1,2.0000,0.0000,2.0000,false
+			2,6.0000,0.0000,6.0000,false",
+			&Config {
+				opening_balance: Some(5.0),
+				..Default::default()
+			},
+		);
+	}
+
+	// Only the selected columns are written in the selected order
+	#[test]
+	fn columns() {
+		let (input, _) = load_samples!("simple");
+		compare_with(
+			input,
+			"total,client
+			1.5000,1
+			2.0000,2",
+			&Config {
+				columns: Some(vec![OutColumn::Total, OutColumn::Client]),
+				..Default::default()
+			},
+		);
+	}
+
+	// Rows are parsed without being applied
+	#[test]
+	fn read_rows() {
+		let (input, _) = load_samples!("simple");
+		let rows = super::read_rows(input.as_bytes())
+			.collect::<Result<Vec<_>, _>>()
+			.unwrap();
+		let row = |typ, client, tx, amount: &str| InRow {
+			typ,
+			client,
+			tx,
+			amount: Some(amount.into()),
+			reason: None,
+		};
+		assert_eq!(
+			rows,
+			[
+				row(TxType::Deposit, 1, 1, "1.0"),
+				row(TxType::Deposit, 2, 2, "2.0"),
+				row(TxType::Deposit, 1, 3, "2.0"),
+				row(TxType::Withdrawal, 1, 4, "1.5"),
+				row(TxType::Withdrawal, 2, 5, "3.0"),
+			]
+		);
+
+		let mut rows = super::read_rows(
+			"type, client, tx, amount
+			deposit, 1, x, 1.0"
+				.as_bytes(),
+		);
+		assert!(matches!(rows.next(), Some(Err(ProcessError::Csv(_)))));
+	}
+
+	// Overflowing transactions are applied with clamped balances and reported
+	#[test]
+	fn overflow_clamped() {
+		const INPUT: &str = "type, client, tx, amount
+			deposit, 1, 1, 900000000000000
+			deposit, 1, 2, 900000000000000
+			dispute, 1, 2,";
+		let conf = Config {
+			on_overflow: OverflowPolicy::ClampAndReport,
+			minor_units: true,
+			..Default::default()
+		};
+
+		compare_with(
+			INPUT,
+			"client,available,held,total,locked
+			1,223372036854775807,9000000000000000000,9223372036854775807,false",
+			&conf,
+		);
+		let report =
+			read_accounts(&mut HashMap::new(), &mut Cursor::new(INPUT), &conf)
+				.unwrap();
+		assert!(report.rejections.is_empty());
+		assert_eq!(
+			report.clamped,
+			[Rejection {
+				line: 3,
+				typ: TxType::Deposit,
+				client: 1,
+				tx: 2,
+				reason: RejectReason::Overflow,
+			}]
+		);
+	}
+
+	// Resolve overflowing the available funds leaves the dispute open
+	#[test]
+	fn overflow_resolve() {
+		const INPUT: &str = "type, client, tx, amount
+			deposit, 1, 1, 900000000000000
+			dispute, 1, 1,
+			deposit, 1, 2, 900000000000000
+			resolve, 1, 1,";
+
+		let mut accounts = HashMap::new();
+		read_accounts(
+			&mut accounts,
+			&mut Cursor::new(INPUT),
+			&Default::default(),
+		)
+		.unwrap();
+		let acc = &accounts[&1];
+		assert_eq!(
+			balances(acc),
+			(9_000_000_000_000_000_000, 9_000_000_000_000_000_000, false)
+		);
+		assert_eq!(acc.deposits[&1].dispute_state, DisputeState::Initiated);
+		assert_rejected(
+			INPUT,
+			Default::default(),
+			Rejection {
+				line: 5,
+				typ: TxType::Resolve,
+				client: 1,
+				tx: 1,
+				reason: RejectReason::Overflow,
+			},
+		);
+	}
+
+	// Second dispute of the same transaction is reported
+	#[test]
+	fn duplicate_dispute() {
+		const INPUT: &str = "type, client, tx, amount
+			deposit, 1, 1, 1.0
+			deposit, 1, 2, 2.0
+			dispute, 1, 1,
+			dispute, 1, 1,";
+
+		compare_with(
+			INPUT,
+			"client,available,held,total,locked
+			1,2.0000,1.0000,3.0000,false",
+			&Default::default(),
+		);
+		assert_rejected(
+			INPUT,
+			Default::default(),
+			Rejection {
+				line: 5,
+				typ: TxType::Dispute,
+				client: 1,
+				tx: 1,
+				reason: RejectReason::DuplicateDispute,
+			},
+		);
+	}
+
+	/// Records the output written by each flush
+	#[derive(Default)]
+	struct Flushes {
+		buf: Vec<u8>,
+		flushed: Vec<String>,
+	}
+
+	impl Write for Flushes {
+		fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+			self.buf.extend_from_slice(buf);
+			Ok(buf.len())
+		}
+
+		fn flush(&mut self) -> io::Result<()> {
+			if !self.buf.is_empty() {
+				self.flushed.push(
+					String::from_utf8(std::mem::take(&mut self.buf)).unwrap(),
+				);
+			}
+			Ok(())
+		}
+	}
+
+	// Finalized accounts are written before the end of input
+	#[test]
+	fn progressive() {
+		let mut res = Flushes::default();
+		let report = process_progressive(
+			&mut res,
+			&mut Cursor::new(
+				"type, client, tx, amount
+				deposit, 1, 1, 1.0
+				deposit, 2, 2, 2.0
+				deposit, 3, 3, 3.0
+				finalize, 2, 0,
+				dispute, 2, 2,
+				deposit, 1, 4, 1.0
+				finalize, 1, 0,
+				withdrawal, 3, 5, 1.0",
+			),
+			&Default::default(),
+		)
+		.unwrap();
+
+		assert_eq!(
+			res.flushed,
+			[
+				"client,available,held,total,locked\n2,2.0000,0.0000,2.0000,false\n",
+				"1,2.0000,0.0000,2.0000,false\n",
+				"3,2.0000,0.0000,2.0000,false\n",
+			]
+		);
+		assert_eq!(
+			report.rejections,
+			[Rejection {
+				line: 6,
+				typ: TxType::Dispute,
+				client: 2,
+				tx: 2,
+				reason: RejectReason::AccountFinalized,
+			}]
+		);
+	}
+
+	// Accounts of client-sorted input are written on each client change
+	#[test]
+	fn sorted_by_client() {
+		let conf = Config {
+			sorted_by_client: true,
+			..Default::default()
+		};
+		let mut res = Flushes::default();
+		process_progressive(
+			&mut res,
+			&mut Cursor::new(
+				"type, client, tx, amount
+				deposit, 2, 1, 2.0
+				withdrawal, 2, 2, 0.5
+				deposit, 1, 3, 1.0
+				deposit, 3, 4, 3.0",
+			),
+			&conf,
+		)
+		.unwrap();
+		assert_eq!(
+			res.flushed,
+			[
+				"client,available,held,total,locked\n2,1.5000,0.0000,1.5000,false\n",
+				"1,1.0000,0.0000,1.0000,false\n",
+				"3,3.0000,0.0000,3.0000,false\n",
+			]
+		);
+
+		let res = process_progressive(
+			&mut io::sink(),
+			&mut Cursor::new(
+				"type, client, tx, amount
+				deposit, 1, 1, 1.0
+				deposit, 2, 2, 2.0
+				dispute, 1, 1,",
+			),
+			&conf,
+		);
+		assert!(matches!(
+			res,
+			Err(ProcessError::Ungrouped { line: 4, client: 1 })
+		));
+	}
+
+	// Amounts more precise than minor units are reported
+	#[test]
+	fn precision_warning() {
+		let report = process(
+			&mut io::sink(),
+			&mut Cursor::new(
+				"type, client, tx, amount
+				deposit, 1, 1, 1.00004
+				deposit, 1, 2, 1.000001
+				withdrawal, 1, 3, 0.5",
+			),
+			&Config {
+				precision_warning: Some(0.000005),
+				..Default::default()
+			},
+		)
+		.unwrap();
+		assert_eq!(
+			report.precision_warnings,
+			[PrecisionWarning {
+				line: 2,
+				typ: TxType::Deposit,
+				client: 1,
+				tx: 1,
+				amount: 1.00004,
+				rounded: 1.0,
+			}]
+		);
+	}
+
+	// Files of disjoint clients processed concurrently match sequential
+	// processing
+	#[test]
+	fn files_parallel() {
+		let paths = [
+			"type, client, tx, amount
+			deposit, 1, 1, 1.0
+			deposit, 2, 2, 2.0
+			dispute, 1, 1,
+			chargeback, 1, 1,",
+			"type, client, tx, amount
+			deposit, 3, 3, 3.0
+			withdrawal, 3, 4, 1.0
+			deposit, 4, 5, 1.0
+			dispute, 4, 5,",
+			"type, client, tx, amount
+			deposit, 5, 6, 2.0
+			deposit, 6, 7, 1.0",
+		]
+		.iter()
+		.enumerate()
+		.map(|(i, content)| {
+			let path = std::env::temp_dir().join(format!(
+				"rust-test-files-parallel-{}-{}.csv",
+				std::process::id(),
+				i
+			));
+			std::fs::write(&path, content).unwrap();
+			path
+		})
+		.collect::<Vec<_>>();
+
+		let mut sequential = HashMap::new();
+		for p in &paths {
+			read_file_accounts(
+				&mut sequential,
+				&File::open(p).unwrap(),
+				&Config::default(),
+			)
+			.unwrap();
+		}
+		let res = [None, NonZeroUsize::new(1), NonZeroUsize::new(2)].map(
+			|max_threads| {
+				read_files_parallel(
+					&paths,
+					&Config {
+						max_threads,
+						..Default::default()
+					},
+				)
+			},
+		);
+		for p in &paths {
+			std::fs::remove_file(p).unwrap();
+		}
+
+		for res in res {
+			let (accounts, report) = res.unwrap();
+			assert!(report.rejections.is_empty());
+			assert_eq!(accounts, sequential);
+		}
+	}
+
+	// Files are processed in parallel, but no more than the configured number
+	// at once
+	#[test]
+	fn files_parallel_max_threads() {
+		use std::sync::{
+			atomic::{AtomicUsize, Ordering},
+			Arc,
+		};
+
+		/// Records the peak number of concurrent parses, which is at most
+		/// the number of concurrently processed files
+		#[derive(Default)]
+		struct Counting {
+			current: AtomicUsize,
+			peak: Arc<AtomicUsize>,
+		}
+
+		impl AmountParser for Counting {
+			fn parse(&self, s: &str) -> Result<f64, String> {
+				let n = self.current.fetch_add(1, Ordering::SeqCst) + 1;
+				self.peak.fetch_max(n, Ordering::SeqCst);
+				std::thread::sleep(std::time::Duration::from_millis(10));
+				self.current.fetch_sub(1, Ordering::SeqCst);
+				DecimalAmountParser.parse(s)
+			}
+		}
+
+		let paths = (0..8)
+			.map(|i| {
+				let path = std::env::temp_dir().join(format!(
+					"rust-test-files-parallel-max-threads-{}-{}.csv",
+					std::process::id(),
+					i
+				));
+				std::fs::write(
+					&path,
+					format!(
+						"type, client, tx, amount\n{}",
+						"deposit, 1, 1, 1.0\n".repeat(5)
+					),
+				)
+				.unwrap();
+				path
+			})
+			.collect::<Vec<_>>();
+
+		let res = [1, 2, 4].map(|max_threads| {
+			let parser = Counting::default();
+			let peak = parser.peak.clone();
+			read_files_parallel(
+				&paths,
+				&Config {
+					max_threads: NonZeroUsize::new(max_threads),
+					amount_parser: Some(Box::new(parser)),
+					..Default::default()
+				},
+			)
+			.unwrap();
+			(max_threads, peak.load(Ordering::SeqCst))
+		});
+		for p in &paths {
+			std::fs::remove_file(p).unwrap();
+		}
+
+		// With more files than threads, every allowed thread is kept busy long
+		// enough for parses to overlap
+		for (max_threads, peak) in res {
+			if max_threads == 1 {
+				assert_eq!(peak, 1, "{} workers with --threads 1", peak);
+			} else {
+				assert!(
+					(2..=max_threads).contains(&peak),
+					"{} workers with --threads {}",
+					peak,
+					max_threads
+				);
+			}
+		}
+	}
+
+	// CSV files of a directory are processed in file name order
+	#[test]
+	fn read_dir() {
+		let dir = std::env::temp_dir()
+			.join(format!("rust-test-read-dir-{}", std::process::id()));
+		std::fs::create_dir(&dir).unwrap();
+		for (name, content) in [
+			("3.csv", "deposit, 1, 3, 1.0"),
+			("1.csv", "deposit, 1, 1, 1.0"),
+			("notes.txt", "not, a, transaction"),
+			("2.csv", "withdrawal, 1, 2, 1.5"),
+		] {
+			std::fs::write(
+				dir.join(name),
+				format!("type, client, tx, amount\n{}", content),
+			)
+			.unwrap();
+		}
+
+		let mut accounts = HashMap::new();
+		let res = read_dir_accounts(&mut accounts, &dir, &Default::default());
+		std::fs::remove_dir_all(&dir).unwrap();
+		assert!(res.unwrap().rejections.is_empty());
+
+		// Withdrawal precedes the second deposit and has insufficient funds
+		assert_eq!(balances(&accounts[&1]), (20_000, 0, false));
+	}
+
+	// State carries across readers of different kinds
+	#[test]
+	fn process_readers() {
+		let path = std::env::temp_dir().join(format!(
+			"rust-test-process-readers-{}.csv",
+			std::process::id()
+		));
+		std::fs::write(
+			&path,
+			"type, client, tx, amount
+			withdrawal, 1, 3, 1.5
+			dispute, 2, 2,",
+		)
+		.unwrap();
+
+		let mut res = Vec::new();
+		let report = super::process_readers(
+			&mut res,
+			vec![
+				Box::new(Cursor::new(
+					"type, client, tx, amount
+					deposit, 1, 1, 2.0
+					deposit, 2, 2, 1.0",
+				)),
+				Box::new(std::fs::File::open(&path).unwrap()),
+			],
+			&Default::default(),
+		)
+		.unwrap();
+		std::fs::remove_file(&path).unwrap();
+
+		assert!(report.rejections.is_empty());
+		compare_output(
+			&res,
+			"client,available,held,total,locked
+			1,0.5000,0.0000,0.5000,false
+			2,0.0000,1.0000,1.0000,false",
+		);
+	}
+
+	// Deposit above the configured maximum amount
+	#[test]
+	fn max_amount() {
+		assert_rejected(
+			"type, client, tx, amount
+			deposit, 1, 1, 100.0
+			deposit, 1, 2, 1e30",
+			Config {
+				max_amount: Some(100.0),
+				..Default::default()
+			},
+			Rejection {
+				line: 3,
+				typ: TxType::Deposit,
+				client: 1,
+				tx: 2,
+				reason: RejectReason::AmountTooLarge,
+			},
+		);
+	}
+
+	// Account state traced after each applied transaction
+	#[test]
+	fn trace() {
+		let (input, _) = load_samples!("disputes");
+		let conf = Config::default();
+		let mut trace = vec![];
+		read_accounts_with(
+			&mut HashMap::new(),
+			&mut Cursor::new(input),
+			&conf,
+			|applied| trace.push(format_trace(applied, &conf)),
+		)
+		.unwrap();
+		assert_eq!(
+			&trace[3..8],
+			[
+				"line 5: dispute of tx 3 for client 1: available 1.0000, \
+				held 2.0000, locked false",
+				"line 6: resolve of tx 3 for client 1: available 3.0000, \
+				held 0.0000, locked false",
+				"line 7: dispute of tx 1 for client 1: available 2.0000, \
+				held 1.0000, locked false",
+				"line 8: dispute of tx 2 for client 2: available 0.0000, \
+				held 2.0000, locked false",
+				"line 9: chargeback of tx 2 for client 2: available 0.0000, \
+				held 0.0000, locked true",
+			]
+		);
+	}
+
+	// Every input row echoed with its outcome
+	#[test]
+	fn trace_rows() {
+		let mut trace = vec![];
+		read_accounts_traced(
+			&mut HashMap::new(),
+			&mut Cursor::new(
+				"type, client, tx, amount
+				deposit, 1, 1, 2.0
+				withdrawal, 1, 2, 5.0
+				dispute, 1, 1,
+				dispute, 1, 1,
+				dispute, 1, 9,
+				deposit, 1, x, 1.0",
+			),
+			&Config {
+				report_refused_withdrawals: true,
+				collect_errors: true,
+				..Default::default()
+			},
+			|s| trace.push(s.to_owned()),
+		)
+		.unwrap();
+		assert_eq!(
+			trace,
+			[
+				"line 2: deposit,1,1,2.0",
+				"line 2: deposit of tx 1 for client 1: available 2.0000, \
+				held 0.0000, locked false",
+				"line 3: withdrawal,1,2,5.0",
+				"line 3: withdrawal of tx 2 for client 1 rejected: \
+				insufficient_funds",
+				"line 4: dispute,1,1,",
+				"line 4: dispute of tx 1 for client 1: available 0.0000, \
+				held 2.0000, locked false",
+				"line 5: dispute,1,1,",
+				"line 5: dispute of tx 1 for client 1 rejected: \
+				duplicate_dispute",
+				"line 6: dispute,1,9,",
+				"line 6: not applied",
+				"line 7: deposit,1,x,1.0",
+				"line 7: field 2: invalid digit found in string",
+			]
+		);
+	}
+
+	// Amounts of a currency with a non-decimal subdivision
+	#[test]
+	fn units_per_major() {
+		const INPUT: &str = "type, client, tx, amount
+			deposit, 1, 1, 1.125
+			deposit, 2, 2, 0.3
+			deposit, 2, 3, 1.0
+			withdrawal, 2, 4, 0.375";
+		let conf = |minor_units| Config {
+			units_per_major: NonZeroU32::new(8),
+			minor_units,
+			..Default::default()
+		};
+
+		// 0.3 is rounded to the nearest eighth
+		compare_with(
+			INPUT,
+			"client,available,held,total,locked
+			1,1.1250,0.0000,1.1250,false
+			2,0.8750,0.0000,0.8750,false",
+			&conf(false),
+		);
+		compare_with(
+			INPUT,
+			"client,available,held,total,locked
+			1,9,0,9,false
+			2,7,0,7,false",
+			&conf(true),
+		);
+	}
+
+	// Chargeback moves funds without locking the account
+	#[test]
+	fn no_lock_on_chargeback() {
+		compare_with(
+			"type, client, tx, amount
+			deposit, 1, 1, 1.0
+			deposit, 1, 2, 2.0
+			dispute, 1, 1,
+			chargeback, 1, 1,
+			withdrawal, 1, 3, 0.5",
+			"client,available,held,total,locked
+			1,1.5000,0.0000,1.5000,false",
+			&Config {
+				no_lock_on_chargeback: true,
+				..Default::default()
+			},
+		);
+	}
+
+	// Resolve of a deposit that was never disputed
+	#[test]
+	fn resolve_without_dispute() {
+		const INPUT: &str = "type, client, tx, amount
+			deposit, 1, 1, 2.0
+			resolve, 1, 1,";
+		let rejection = Rejection {
+			line: 3,
+			typ: TxType::Resolve,
+			client: 1,
+			tx: 1,
+			reason: RejectReason::ResolveWithoutDispute,
+		};
+		assert_rejected(
+			INPUT,
+			Config {
+				reject_undisputed_resolves: true,
+				..Default::default()
+			},
+			rejection.clone(),
+		);
+		assert!(matches!(
+			process(
+				&mut io::sink(),
+				&mut Cursor::new(INPUT),
+				&Config {
+					strict: true,
+					..Default::default()
+				},
+			),
+			Err(ProcessError::InvalidRow(rej)) if rej == rejection
+		));
+
+		let report = process(
+			&mut io::sink(),
+			&mut Cursor::new(INPUT),
+			&Config::default(),
+		)
+		.unwrap();
+		assert!(report.rejections.is_empty());
+	}
+
+	// Administrative adjustments of the available funds
+	#[test]
+	fn adjustment() {
+		const INPUT: &str = "type, client, tx, amount, reason
+			deposit, 1, 1, 2.0,
+			adjustment, 1, 2, 1.5, fee refund
+			adjustment, 1, 3, -0.5, correction
+			adjustment, 1, 4, -5.0, overdraw
+			dispute, 1, 2,,";
+		let mut res = vec![];
+		let report = process(
+			&mut Cursor::new(&mut res),
+			&mut Cursor::new(INPUT),
+			&Config {
+				allow_admin: true,
+				..Default::default()
+			},
+		)
+		.unwrap();
+		compare_output(
+			&res,
+			"client,available,held,total,locked
+			1,3.0000,0.0000,3.0000,false",
+		);
+		assert_eq!(
+			report.adjustments,
+			[
+				Adjustment {
+					line: 3,
+					client: 1,
+					tx: 2,
+					amount: 1.5,
+					reason: Some("fee refund".into()),
+				},
+				Adjustment {
+					line: 4,
+					client: 1,
+					tx: 3,
+					amount: -0.5,
+					reason: Some("correction".into()),
+				},
+			]
+		);
+		assert_eq!(
+			report.rejections,
+			[Rejection {
+				line: 5,
+				typ: TxType::Adjustment,
+				client: 1,
+				tx: 4,
+				reason: RejectReason::InsufficientFunds,
+			}]
+		);
+
+		assert_rejected(
+			"type, client, tx, amount
+			adjustment, 1, 1, 1.0",
+			Config::default(),
+			Rejection {
+				line: 2,
+				typ: TxType::Adjustment,
+				client: 1,
+				tx: 1,
+				reason: RejectReason::AdminNotAllowed,
+			},
+		);
+	}
+
+	// Scale of 2 decimal amounts detected and used for output
+	#[test]
+	fn detect_scale() {
+		const INPUT: &str = "type, client, tx, amount
+			deposit, 1, 1, 1.5
+			deposit, 1, 2, 2.25
+			dispute, 1, 2,
+			withdrawal, 1, 3, 1";
+		let scale =
+			super::detect_scale(INPUT.as_bytes(), 10, &Default::default());
+		assert_eq!(scale, Some(2));
+		compare_with(
+			INPUT,
+			"client,available,held,total,locked
+			1,0.50,2.25,2.75,false",
+			&Config {
+				scale,
+				..Default::default()
+			},
+		);
+
+		// Exponents and excessive precision are ambiguous
+		for amount in ["1e3", "1.23456"] {
+			let input =
+				format!("type,client,tx,amount\ndeposit,1,1,{}", amount);
+			assert_eq!(
+				super::detect_scale(input.as_bytes(), 10, &Default::default()),
+				None
+			);
+		}
+	}
+
+	// Deposit below the configured minimum amount
+	#[test]
+	fn min_deposit() {
+		let conf = || Config {
+			min_deposit: Some(1.0),
+			..Default::default()
+		};
+		assert_rejected(
+			"type, client, tx, amount
+			deposit, 1, 1, 1.0
+			deposit, 1, 2, 0.5
+			withdrawal, 1, 3, 0.25",
+			conf(),
+			Rejection {
+				line: 3,
+				typ: TxType::Deposit,
+				client: 1,
+				tx: 2,
+				reason: RejectReason::BelowMinDeposit,
+			},
+		);
+		compare_with(
+			"type, client, tx, amount
+			deposit, 1, 1, 1.0
+			deposit, 1, 2, 0.5
+			withdrawal, 1, 3, 0.25",
+			"client,available,held,total,locked
+			1,0.7500,0.0000,0.7500,false",
+			&conf(),
+		);
+	}
+
+	// Stream of applied transaction effects
+	#[test]
+	fn process_with_events() {
+		let (input, _) = load_samples!("disputes");
+		let conf = Config::default();
+		let events = super::process_with_events(input.as_bytes(), &conf)
+			.collect::<Result<Vec<_>, _>>()
+			.unwrap();
+
+		let mut applied = vec![];
+		let mut accounts = HashMap::new();
+		read_accounts_with(
+			&mut accounts,
+			&mut Cursor::new(input),
+			&conf,
+			|tx| applied.push(TxEvent::from(tx)),
+		)
+		.unwrap();
+		assert_eq!(events, applied);
+
+		// One event per applied transaction. The withdrawal from the locked
+		// account of client 2 has no effect.
+		assert_eq!(events.len(), 10);
+		assert_eq!(
+			events.len() as u64,
+			accounts.values().map(|acc| acc.applied).sum::<u64>()
+		);
+		assert!(events.iter().all(|e| e.line != 11));
+		assert_eq!(
+			events[7],
+			TxEvent {
+				line: 9,
+				typ: TxType::Chargeback,
+				client: 2,
+				tx: 2,
+				available_delta: 0,
+				held_delta: -20_000,
+				available: 0,
+				held: 0,
+				locked: true,
+			}
+		);
+
+		let mut events = super::process_with_events(
+			"type, client, tx, amount
+			deposit, 1, 1, 1.0
+			deposit, 1, x, 1.0"
+				.as_bytes(),
+			&conf,
+		);
+		assert!(matches!(events.next(), Some(Ok(TxEvent { tx: 1, .. }))));
+		assert!(matches!(events.next(), Some(Err(ProcessError::Csv(_)))));
+		assert!(events.next().is_none());
+	}
+
+	// Dispute preceding its deposit applied within the grace window
+	#[test]
+	fn dispute_grace() {
+		const INPUT: &str = "type, client, tx, amount
+			dispute, 1, 1,
+			deposit, 1, 2, 1.0
+			deposit, 1, 1, 2.0
+			dispute, 1, 3,
+			deposit, 1, 4, 1.0
+			deposit, 1, 5, 1.0
+			deposit, 1, 3, 1.0";
+		let conf = |dispute_grace| Config {
+			dispute_grace,
+			..Default::default()
+		};
+
+		// Dispute of tx 3 expired before its deposit
+		compare_with(
+			INPUT,
+			"client,available,held,total,locked
+			1,4.0000,2.0000,6.0000,false",
+			&conf(Some(2)),
+		);
+		compare_with(
+			INPUT,
+			"client,available,held,total,locked
+			1,6.0000,0.0000,6.0000,false",
+			&conf(None),
+		);
+
+		// Held disputes are counted once applied and rejected once expired,
+		// including ones still held at the end of input
+		let report = process(
+			&mut io::sink(),
+			&mut Cursor::new(INPUT.to_owned() + "\ndispute, 1, 9,"),
+			&conf(Some(2)),
+		)
+		.unwrap();
+		let rejection = |line, tx| Rejection {
+			line,
+			typ: TxType::Dispute,
+			client: 1,
+			tx,
+			reason: RejectReason::DisputeGraceExpired,
+		};
+		assert_eq!(report.rejections, [rejection(5, 3), rejection(9, 9)]);
+		let stats = report.tx_stats[&TxType::Dispute];
+		assert_eq!((stats.read, stats.applied, stats.rejected), (3, 1, 2));
+	}
+
+	// Withdrawals refused due to a locked account and insufficient funds
+	#[test]
+	fn refused_withdrawals() {
+		const INPUT: &str = "type, client, tx, amount
+			deposit, 1, 1, 2.0
+			deposit, 1, 2, 1.0
+			dispute, 1, 1,
+			chargeback, 1, 1,
+			withdrawal, 1, 3, 0.5
+			deposit, 2, 4, 1.0
+			withdrawal, 2, 5, 1.5";
+		let report = process(
+			&mut io::sink(),
+			&mut Cursor::new(INPUT),
+			&Config {
+				report_refused_withdrawals: true,
+				..Default::default()
+			},
+		)
+		.unwrap();
+		assert_eq!(
+			report.rejections,
+			[
+				Rejection {
+					line: 6,
+					typ: TxType::Withdrawal,
+					client: 1,
+					tx: 3,
+					reason: RejectReason::LockedAccount,
+				},
+				Rejection {
+					line: 8,
+					typ: TxType::Withdrawal,
+					client: 2,
+					tx: 5,
+					reason: RejectReason::InsufficientFunds,
+				},
+			]
+		);
+
+		let report = process(
+			&mut io::sink(),
+			&mut Cursor::new(INPUT),
+			&Config::default(),
+		)
+		.unwrap();
+		assert!(report.rejections.is_empty());
+	}
+
+	// Charged back deposits pruned from or retained in the registry
+	#[test]
+	fn charged_back_deposits() {
+		for (mode, deposits) in [
+			(ChargedBackDeposits::Retain, vec![1, 2]),
+			(ChargedBackDeposits::Prune, vec![2]),
+		] {
+			let conf = Config {
+				charged_back: mode,
+				..Default::default()
+			};
+			let mut accounts = HashMap::new();
+			read_accounts(
+				&mut accounts,
+				&mut Cursor::new(
+					"type, client, tx, amount
+					deposit, 1, 1, 2.0
+					deposit, 1, 2, 1.0
+					dispute, 1, 1,
+					chargeback, 1, 1,",
+				),
+				&conf,
+			)
+			.unwrap();
+
+			let acc = &accounts[&1];
+			let mut txs = acc.deposits.keys().copied().collect::<Vec<_>>();
+			txs.sort_unstable();
+			assert_eq!(txs, deposits);
+			assert_eq!(balances(acc), (10_000, 0, true));
+		}
+	}
+
+	// Amounts violating the sign conventions of their transaction type
+	#[test]
+	fn validate_signs() {
+		let mut res = vec![];
+		let report = process(
+			&mut Cursor::new(&mut res),
+			&mut Cursor::new(
+				"type, client, tx, amount
+				deposit, 1, 1, 2.0
+				deposit, 1, 2, -1.0
+				dispute, 1, 1, 2.0
+				withdrawal, 1, 3, 0
+				dispute, 1, 1,",
+			),
+			&Config {
+				validate_signs: true,
+				..Default::default()
+			},
+		)
+		.unwrap();
+		compare_output(
+			&res,
+			"client,available,held,total,locked
+			1,0.0000,2.0000,2.0000,false",
+		);
+		assert_eq!(
+			report
+				.rejections
+				.iter()
+				.map(|rej| (rej.line, rej.reason))
+				.collect::<Vec<_>>(),
+			[
+				(3, RejectReason::NonPositiveAmount),
+				(4, RejectReason::UnexpectedAmount),
+				(5, RejectReason::NonPositiveAmount),
+			]
+		);
+	}
+
+	// Dispute of a withdrawal reported instead of ignored
+	#[test]
+	fn withdrawal_dispute() {
+		const INPUT: &str = "type, client, tx, amount
+			deposit, 1, 1, 2.0
+			withdrawal, 1, 2, 1.0
+			dispute, 1, 2,
+			dispute, 1, 3,";
+		assert_rejected(
+			INPUT,
+			Config {
+				detect_withdrawal_disputes: true,
+				..Default::default()
+			},
+			Rejection {
+				line: 4,
+				typ: TxType::Dispute,
+				client: 1,
+				tx: 2,
+				reason: RejectReason::DisputeOnWithdrawalUnsupported,
+			},
+		);
+
+		let report = process(
+			&mut io::sink(),
+			&mut Cursor::new(INPUT),
+			&Config::default(),
+		)
+		.unwrap();
+		assert!(report.rejections.is_empty());
+	}
+
+	// Withdrawals leaving a dust balance
+	#[test]
+	fn min_residual() {
+		let conf = || Config {
+			min_residual: Some(0.5),
+			..Default::default()
+		};
+		assert_rejected(
+			"type, client, tx, amount
+			deposit, 1, 1, 2.0
+			withdrawal, 1, 2, 1.75",
+			conf(),
+			Rejection {
+				line: 3,
+				typ: TxType::Withdrawal,
+				client: 1,
+				tx: 2,
+				reason: RejectReason::DustBalance,
+			},
+		);
+		compare_with(
+			"type, client, tx, amount
+			deposit, 1, 1, 2.0
+			withdrawal, 1, 2, 1.5
+			deposit, 2, 3, 1.0
+			withdrawal, 2, 4, 1.0",
+			"client,available,held,total,locked
+			1,0.5000,0.0000,0.5000,false
+			2,0.0000,0.0000,0.0000,false",
+			&conf(),
+		);
+	}
+
+	// Account rows written to a custom sink
+	#[test]
+	fn output_sink() {
+		#[derive(Default)]
+		struct Collect {
+			rows: Vec<(u16, String, bool)>,
+			finished: bool,
+		}
+
+		impl OutputSink for &mut Collect {
+			fn write_account(
+				&mut self,
+				row: &OutRow,
+			) -> Result<(), ProcessError> {
+				self.rows.push((row.client, row.total.clone(), row.locked));
+				Ok(())
+			}
+
+			fn finish(self) -> Result<(), ProcessError> {
+				self.finished = true;
+				Ok(())
+			}
+		}
+
+		let (input, _) = load_samples!("disputes");
+		let mut sink = Collect::default();
+		process_to(&mut sink, &mut Cursor::new(input), &Default::default())
+			.unwrap();
+		sink.rows.sort_unstable_by_key(|(cl, ..)| *cl);
+		assert_eq!(
+			sink.rows,
+			[(1, "1.5000".into(), false), (2, "5.0000".into(), true)]
+		);
+		assert!(sink.finished);
+
+		// Built-in sinks match the built-in output formats
+		let conf = |output_format| Config {
+			output_format,
+			sort_first_seen: true,
+			output_delimiter: Some(b';'),
+			scale_header: true,
+			grand_total: true,
+			checksum: true,
+			..Default::default()
+		};
+		let expected = |format| {
+			let mut res = vec![];
+			process(&mut res, &mut Cursor::new(input), &conf(format)).unwrap();
+			res
+		};
+		let mut res = vec![];
+		process_to(
+			CsvSink::new(&mut res, &conf(OutputFormat::Csv)).unwrap(),
+			&mut Cursor::new(input),
+			&conf(OutputFormat::Csv),
+		)
+		.unwrap();
+		assert_eq!(res, expected(OutputFormat::Csv));
+		let mut res = vec![];
+		process_to(
+			JsonSink::new(&mut res),
+			&mut Cursor::new(input),
+			&conf(OutputFormat::Json),
+		)
+		.unwrap();
+		assert_eq!(res, expected(OutputFormat::Json));
+	}
+
+	// Deposit registry of a client written for investigation
+	#[test]
+	fn ledger() {
+		let mut accounts = HashMap::new();
+		read_accounts(
+			&mut accounts,
+			&mut Cursor::new(
+				"type, client, tx, amount
+				deposit, 1, 3, 1.0
+				deposit, 1, 1, 2.0
+				deposit, 1, 2, 0.5
+				deposit, 2, 4, 1.0
+				dispute, 1, 1,
+				dispute, 1, 3,
+				chargeback, 1, 3,",
+			),
+			&Config::default(),
+		)
+		.unwrap();
+
+		let mut res = vec![];
+		write_ledger(&mut res, &accounts[&1], &Config::default()).unwrap();
+		compare_output(
+			&res,
+			"tx,amount,dispute_state
+			1,2.0000,initiated
+			2,0.5000,not_initiated
+			3,1.0000,charged_back",
+		);
+	}
+
+	// Amount scale recorded in a comment line before the header
+	#[test]
+	fn scale_header() {
+		const INPUT: &str = "type, client, tx, amount
+			deposit, 1, 1, 1.5";
+		compare_with(
+			INPUT,
+			"# scale=4 units_per_major=10000
+			client,available,held,total,locked
+			1,1.5000,0.0000,1.5000,false",
+			&Config {
+				scale_header: true,
+				..Default::default()
+			},
+		);
+		compare_with(
+			INPUT,
+			"# scale=0 units_per_major=8
+			client,available,held,total,locked
+			1,12,0,12,false",
+			&Config {
+				scale_header: true,
+				minor_units: true,
+				units_per_major: NonZeroU32::new(8),
+				..Default::default()
+			},
+		);
+	}
+
+	// Locked column written in each bool style
+	#[test]
+	fn bool_style() {
+		for (style, locked, unlocked) in [
+			("true/false", "true", "false"),
+			("1/0", "1", "0"),
+			("yes/no", "yes", "no"),
+		] {
+			compare_with(
+				"type, client, tx, amount
+				deposit, 1, 1, 1.0
+				deposit, 2, 2, 1.0
+				dispute, 2, 2,
+				chargeback, 2, 2,",
+				&format!(
+					"client,available,held,total,locked
+					1,1.0000,0.0000,1.0000,{}
+					2,0.0000,0.0000,0.0000,{}",
+					unlocked, locked
+				),
+				&Config {
+					bool_style: style.parse().unwrap(),
+					..Default::default()
+				},
+			);
+		}
+	}
+
+	// Client IDs zero-padded to a fixed width
+	#[test]
+	fn client_width() {
+		compare_with(
+			"type, client, tx, amount
+			deposit, 7, 1, 1.0
+			deposit, 1234, 2, 2.0",
+			"client,available,held,total,locked
+			007,1.0000,0.0000,1.0000,false
+			1234,2.0000,0.0000,2.0000,false",
+			&Config {
+				client_width: Some(3),
+				..Default::default()
+			},
+		);
+	}
+
+	// Exact minor unit balances written alongside the formatted ones
+	#[test]
+	fn audit_columns() {
+		compare_with(
+			"type, client, tx, amount
+			deposit, 1, 1, 1.23456
+			deposit, 1, 2, 0.5
+			dispute, 1, 2,",
+			"client,available,held,total,locked,available_minor,held_minor,\
+			total_minor
+			1,1.2346,0.5000,1.7346,false,12346,5000,17346",
+			&Config {
+				audit_columns: true,
+				..Default::default()
+			},
+		);
+	}
+
+	// Reuse of a transaction ID with a different type aborts in strict mode
+	#[test]
+	fn tx_type_conflict() {
+		const INPUT: &str = "type, client, tx, amount
+			deposit, 1, 7, 2.0
+			withdrawal, 1, 7, 1.0";
+		let run = |strict| {
+			process(
+				&mut io::sink(),
+				&mut Cursor::new(INPUT),
+				&Config {
+					strict,
+					..Default::default()
+				},
+			)
+		};
+
+		assert!(run(false).unwrap().rejections.is_empty());
+		match run(true) {
+			Err(ProcessError::InvalidRow(rej)) => assert_eq!(
+				rej,
+				Rejection {
+					line: 3,
+					typ: TxType::Withdrawal,
+					client: 1,
+					tx: 7,
+					reason: RejectReason::TxTypeConflict,
+				}
+			),
+			res => panic!("unexpected result: {:?}", res),
+		}
+	}
+
+	// Disputes holding more than the cap are refused
+	#[test]
+	fn max_held() {
+		const INPUT: &str = "type, client, tx, amount
+			deposit, 1, 1, 1.0
+			deposit, 1, 2, 1.0
+			deposit, 1, 3, 1.0
+			dispute, 1, 1,
+			dispute, 1, 2,
+			dispute, 1, 3,";
+		let conf = || Config {
+			max_held: Some(2.0),
+			..Default::default()
+		};
+
+		compare_with(
+			INPUT,
+			"client,available,held,total,locked
+			1,1.0000,2.0000,3.0000,false",
+			&conf(),
+		);
+		assert_rejected(
+			INPUT,
+			conf(),
+			Rejection {
+				line: 7,
+				typ: TxType::Dispute,
+				client: 1,
+				tx: 3,
+				reason: RejectReason::HoldLimitExceeded,
+			},
+		);
+	}
+
+	// Checksum row matches the independently summed totals
+	#[test]
+	fn checksum() {
+		let (input, _) = load_samples!("disputes");
+		let mut res = Vec::new();
+		process(
+			&mut res,
+			&mut Cursor::new(input),
+			&Config {
+				checksum: true,
+				..Default::default()
+			},
+		)
+		.unwrap();
+
+		let res = std::str::from_utf8(&res).unwrap();
+		let (rows, checksum) = res.trim_end().rsplit_once('\n').unwrap();
+		let rows = rows.lines().skip(1).collect::<Vec<_>>();
+		let total: f64 = rows
+			.iter()
+			.map(|r| r.split(',').nth(3).unwrap().parse::<f64>().unwrap())
+			.sum();
+		assert_eq!(checksum, format!("checksum,{:.4},{}", total, rows.len()));
+
+		// Sum exceeding the range of a balance
+		let mut res = Vec::new();
+		process(
+			&mut res,
+			&mut Cursor::new(
+				"type, client, tx, amount
+				deposit, 1, 1, 900000000000000
+				dispute, 1, 1,
+				deposit, 2, 2, 900000000000000",
+			),
+			&Config {
+				checksum: true,
+				..Default::default()
+			},
+		)
+		.unwrap();
+		assert!(std::str::from_utf8(&res)
+			.unwrap()
+			.ends_with("checksum,1800000000000000.0000,2\n"));
+	}
+
+	// Client 0 as an ordinary client, a system account and rejected
+	#[test]
+	fn client_zero() {
+		const INPUT: &str = "type, client, tx, amount
+			deposit, 0, 1, 1.0
+			deposit, 1, 2, 2.0";
+		for (client_zero, expected, rejected) in [
+			(
+				ClientZero::Ordinary,
+				"client,available,held,total,locked
+				0,1.0000,0.0000,1.0000,false
+				1,2.0000,0.0000,2.0000,false",
+				None,
+			),
+			(
+				ClientZero::System,
+				"client,available,held,total,locked
+				1,2.0000,0.0000,2.0000,false",
+				None,
+			),
+			(
+				ClientZero::Reject,
+				"client,available,held,total,locked
+				0,0.0000,0.0000,0.0000,false
+				1,2.0000,0.0000,2.0000,false",
+				Some(RejectReason::ClientZero),
+			),
+		] {
+			let conf = Config {
+				client_zero,
+				..Default::default()
+			};
+			compare_with(INPUT, expected, &conf);
+
+			let mut accounts = HashMap::new();
+			let report =
+				read_accounts(&mut accounts, &mut Cursor::new(INPUT), &conf)
+					.unwrap();
+			assert_eq!(
+				report
+					.rejections
+					.iter()
+					.map(|r| r.reason)
+					.collect::<Vec<_>>(),
+				rejected.into_iter().collect::<Vec<_>>()
+			);
+			if client_zero == ClientZero::System {
+				assert_eq!(accounts[&0].available, 10_000);
+			}
+		}
+	}
+
+	// Rejected transaction in the middle of the input leaves the accounts
+	// unchanged
+	#[test]
+	fn all_or_nothing() {
+		let mut accounts = HashMap::new();
+		let res = read_accounts(
+			&mut accounts,
+			&mut Cursor::new(
+				"type, client, tx, amount
+				deposit, 1, 1, 1.0
+				deposit, 2, 2,
+				deposit, 3, 3, 3.0",
+			),
+			&Config {
+				all_or_nothing: true,
+				..Default::default()
+			},
+		);
+		assert!(matches!(
+			res,
+			Err(ProcessError::InvalidRow(Rejection {
+				line: 3,
+				reason: RejectReason::MissingAmount,
+				..
+			}))
+		));
+		assert!(accounts.is_empty());
+	}
+
+	// Summary row sums the amount columns
+	#[test]
+	fn grand_total() {
+		let (input, _) = load_samples!("disputes");
+		let mut res = Vec::new();
+		process(
+			&mut res,
+			&mut Cursor::new(input),
+			&Config {
+				grand_total: true,
+				..Default::default()
+			},
+		)
+		.unwrap();
+
+		let res = std::str::from_utf8(&res).unwrap();
+		let (rows, summary) = res.trim_end().rsplit_once('\n').unwrap();
+		let rows = rows.lines().skip(1).collect::<Vec<_>>();
+		let sum = |i| -> f64 {
+			rows.iter()
+				.map(|r| r.split(',').nth(i).unwrap().parse::<f64>().unwrap())
+				.sum()
+		};
+		assert_eq!(
+			summary,
+			format!("TOTAL,{:.4},{:.4},{:.4},", sum(1), sum(2), sum(3))
+		);
+
+		// Sums exceeding the range of a balance
+		let mut res = Vec::new();
+		process(
+			&mut res,
+			&mut Cursor::new(
+				"type, client, tx, amount
+				deposit, 1, 1, 900000000000000
+				deposit, 2, 2, 900000000000000
+				deposit, 3, 3, 900000000000000
+				dispute, 3, 3,
+				deposit, 4, 4, 900000000000000
+				dispute, 4, 4,",
+			),
+			&Config {
+				grand_total: true,
+				..Default::default()
+			},
+		)
+		.unwrap();
+		assert!(std::str::from_utf8(&res).unwrap().ends_with(
+			"TOTAL,1800000000000000.0000,1800000000000000.0000,\
+			3600000000000000.0000,\n"
+		));
+	}
+
+	// Unlocked accounts with no funds omitted
+	#[test]
+	fn suppress_empty() {
+		compare_with(
+			"type, client, tx, amount
+			deposit, 1, 1, 1.0
+			withdrawal, 1, 2, 1.0
+			deposit, 2, 3, 1.0
+			dispute, 2, 3,
+			chargeback, 2, 3,
+			deposit, 3, 4, 1.0",
+			"client,available,held,total,locked
+			2,0.0000,0.0000,0.0000,true
+			3,1.0000,0.0000,1.0000,false",
+			&Config {
+				suppress_empty: true,
+				..Default::default()
+			},
+		);
+	}
+
+	// Accounts of clients only present in rejected or ignored rows omitted
+	#[test]
+	fn suppress_inactive() {
+		compare_with(
+			"type, client, tx, amount
+			deposit, 1, 1, 1.0
+			withdrawal, 1, 2, 1.0
+			withdrawal, 2, 3, 1.0
+			dispute, 3, 1,
+			deposit, 4, 4,",
+			"client,available,held,total,locked
+			1,0.0000,0.0000,0.0000,false",
+			&Config {
+				suppress_inactive: true,
+				..Default::default()
+			},
+		);
+	}
+
+	// Same dispute sequence under both accounting modes
+	#[test]
+	fn accounting() {
+		for (accounting, disputed, charged_back) in [
+			(Accounting::MoveToHeld, (5_000, 20_000), (5_000, 0)),
+			(Accounting::Annotate, (25_000, 20_000), (5_000, 0)),
+		] {
+			let conf = Config {
+				accounting,
+				..Default::default()
+			};
+			let mut acc = Account::default();
+			for tx in [
+				Transaction::Deposit { tx: 1, amount: 2.0 },
+				Transaction::Deposit { tx: 2, amount: 0.5 },
+				Transaction::Dispute { tx: 1 },
+			] {
+				acc.apply_with(&tx, &conf).unwrap();
+			}
+			assert_eq!((acc.available, acc.held), disputed);
+			assert_eq!(acc.total(&conf), 25_000);
+
+			acc.apply_with(
+				&Transaction::Resolve {
+					tx: 1,
+					amount: None,
+				},
+				&conf,
+			)
+			.unwrap();
+			assert_eq!((acc.available, acc.held), (25_000, 0));
+
+			for tx in [
+				Transaction::Dispute { tx: 1 },
+				Transaction::Chargeback {
+					tx: 1,
+					amount: None,
+				},
+			] {
+				acc.apply_with(&tx, &conf).unwrap();
+			}
+			assert_eq!((acc.available, acc.held), charged_back);
+			assert_eq!(acc.total(&conf), 5_000);
+		}
+	}
+
+	// Transaction with a reserved ID
+	#[test]
+	fn reserved_tx() {
+		assert_rejected(
+			"type, client, tx, amount
+			deposit, 1, 1, 1.0
+			deposit, 1, 0, 1.0",
+			Config {
+				reserved_tx: Some(0..=0),
+				..Default::default()
+			},
+			Rejection {
+				line: 3,
+				typ: TxType::Deposit,
+				client: 1,
+				tx: 0,
+				reason: RejectReason::ReservedTxId,
+			},
+		);
+	}
+
+	// Hook called with each applied transaction in input order
+	#[test]
+	fn on_transaction() {
+		let (input, _) = load_samples!("disputes");
+		let mut applied = Vec::new();
+		let report = read_accounts_with(
+			&mut HashMap::new(),
+			&mut Cursor::new(input),
+			&Default::default(),
+			|tx| {
+				applied.push((
+					tx.line,
+					tx.client,
+					tx.transaction.tx(),
+					tx.available_delta,
+					tx.held_delta,
+					tx.account.available,
+				))
+			},
+		)
+		.unwrap();
+		assert!(report.rejections.is_empty());
+
+		// Every row is applied in order, except for the withdrawal from the
+		// locked account of client 2, which has no effect
+		assert_eq!(
+			applied.iter().map(|a| (a.0, a.1, a.2)).collect::<Vec<_>>(),
+			input
+				.lines()
+				.zip(1..)
+				.skip(1)
+				.map(|(l, i)| {
+					let mut f = l.split(',').map(str::trim).skip(1);
+					let mut next = || f.next().unwrap().parse().unwrap();
+					(i, next() as u16, next())
+				})
+				.filter(|&(i, ..)| i != 11)
+				.collect::<Vec<_>>()
+		);
+
+		let mut available = HashMap::<u16, i64>::new();
+		for (_, client, _, delta, _, after) in &applied {
+			let a = available.entry(*client).or_default();
+			*a += delta;
+			assert_eq!(a, after);
+		}
+	}
+
+	// Withdrawals of the entire available amount leave exactly nothing
+	#[test]
+	fn exact_empty_withdrawal() {
+		compare(
+			"type, client, tx, amount
+			deposit, 1, 1, 1.0029
+			withdrawal, 1, 2, 1.0029
+			deposit, 2, 3, 0.0001
+			deposit, 2, 4, 0.0001
+			deposit, 2, 5, 0.0001
+			withdrawal, 2, 6, 0.0003
+			deposit, 3, 7, 0.1
+			deposit, 3, 8, 0.2
+			withdrawal, 3, 9, 0.3",
+			"client,available,held,total,locked
+			1,0.0000,0.0000,0.0000,false
+			2,0.0000,0.0000,0.0000,false
+			3,0.0000,0.0000,0.0000,false",
+		);
+	}
+
+	// Disputes of withdrawals recorded as negative deposits
+	#[test]
+	fn dispute_withdrawals() {
+		let conf = Config {
+			dispute_withdrawals: true,
+			..Default::default()
+		};
+		let mut acc = Account::default();
+		for tx in [
+			Transaction::Deposit { tx: 1, amount: 3.0 },
+			Transaction::Withdrawal { tx: 2, amount: 1.0 },
+			Transaction::Withdrawal { tx: 3, amount: 0.5 },
+			Transaction::Dispute { tx: 2 },
+		] {
+			acc.apply_with(&tx, &conf).unwrap();
+		}
+		assert_eq!(acc.deposits[&2].amount, -10_000);
+		assert_eq!(acc.deposits[&2].dispute_state, DisputeState::Initiated);
+		assert_eq!(balances(&acc), (15_000, 0, false));
+
+		acc.apply_with(
+			&Transaction::Resolve {
+				tx: 2,
+				amount: None,
+			},
+			&conf,
+		)
+		.unwrap();
+		assert_eq!(balances(&acc), (15_000, 0, false));
+
+		for tx in [
+			Transaction::Dispute { tx: 2 },
+			Transaction::Chargeback {
+				tx: 2,
+				amount: Some(0.4),
+			},
+		] {
+			acc.apply_with(&tx, &conf).unwrap();
+		}
+		assert_eq!(balances(&acc), (19_000, 0, true));
+		assert_eq!(
+			acc.apply_with(
+				&Transaction::Chargeback {
+					tx: 2,
+					amount: Some(0.7),
+				},
+				&conf
+			),
+			Err(RejectReason::AmountExceedsDisputed)
+		);
+
+		// Rest of the withdrawal is returned
+		acc.apply_with(
+			&Transaction::Chargeback {
+				tx: 2,
+				amount: None,
+			},
+			&conf,
+		)
+		.unwrap();
+		assert_eq!(balances(&acc), (25_000, 0, true));
+		assert_eq!(acc.deposits[&2].dispute_state, DisputeState::ChargedBack);
+
+		// Recorded transactions are not overwritten by a withdrawal reusing
+		// their ID
+		let mut acc = Account::default();
+		for tx in [
+			Transaction::Deposit { tx: 1, amount: 3.0 },
+			Transaction::Dispute { tx: 1 },
+			Transaction::Deposit { tx: 2, amount: 1.0 },
+		] {
+			acc.apply_with(&tx, &conf).unwrap();
+		}
+		assert_eq!(
+			acc.apply_with(
+				&Transaction::Withdrawal { tx: 1, amount: 0.5 },
+				&conf
+			),
+			Err(RejectReason::DuplicateTx)
+		);
+		assert_eq!(balances(&acc), (10_000, 30_000, false));
+		assert_eq!(acc.deposits[&1].dispute_state, DisputeState::Initiated);
+	}
+
+	// Output amounts in internal minor units
+	#[test]
+	fn minor_units() {
+		let (input, _) = load_samples!("disputes");
+		let mut accounts = HashMap::new();
+		read_accounts(
+			&mut accounts,
+			&mut Cursor::new(input),
+			&Default::default(),
+		)
+		.unwrap();
+		let expected = accounts.iter().fold(
+			"client,available,held,total,locked".to_owned(),
+			|mut w, (cl, acc)| {
+				w += &format!(
+					"\n{},{},{},{},{}",
+					cl,
+					acc.available,
+					acc.held,
+					acc.available + acc.held,
+					acc.locked
+				);
+				w
+			},
+		);
+
+		compare_with(
+			input,
+			&expected,
+			&Config {
+				minor_units: true,
+				..Default::default()
+			},
+		);
+	}
+
+	// Memory-mapped and buffered reads produce identical output
+	#[test]
+	fn mmap() {
+		let path = std::env::temp_dir()
+			.join(format!("rust-test-mmap-{}.csv", std::process::id()));
+		std::fs::write(&path, test_utils::generate_input(1, 20, 10_000))
+			.unwrap();
+
+		let out = |mmap| {
+			let conf = Config {
+				mmap,
+				..Default::default()
+			};
+			let mut accounts = HashMap::new();
+			read_file_accounts(
+				&mut accounts,
+				&std::fs::File::open(&path).unwrap(),
+				&conf,
+			)
+			.unwrap();
+			let mut w = Vec::new();
+			write_accounts(&mut w, accounts, &conf).unwrap();
+			w
+		};
+		let (mapped, buffered) = (out(true), out(false));
+		std::fs::remove_file(&path).unwrap();
+
+		let buffered = std::str::from_utf8(&buffered).unwrap();
+		compare_output(&mapped, buffered);
+	}
+
+	// Only accounts of allowed clients are created
+	#[test]
+	fn client_filter() {
+		let mut accounts = HashMap::new();
+		let report = read_accounts(
+			&mut accounts,
+			&mut Cursor::new(
+				"type, client, tx, amount
+				deposit, 1, 1, 1.0
+				deposit, 2, 2, 2.0
+				deposit, 3, 3, 2.0
+				withdrawal, 1, 4, 0.5",
+			),
+			&Config {
+				clients: ClientFilter::Allow([2].into()),
+				report_skipped: true,
+				..Default::default()
+			},
+		)
+		.unwrap();
+
+		assert_eq!(accounts.keys().collect::<Vec<_>>(), [&2]);
+		assert!(report.rejections.is_empty());
+		assert_eq!(
+			report
+				.skipped
+				.iter()
+				.map(|s| (s.client, s.tx, s.reason))
+				.collect::<Vec<_>>(),
+			[
+				(1, 1, RejectReason::ClientExcluded),
+				(3, 3, RejectReason::ClientExcluded),
+				(1, 4, RejectReason::ClientExcluded),
+			]
+		);
+	}
+
+	// Input with the final row cut off
+	#[test]
+	fn truncated() {
+		let conf = Config {
+			detect_truncation: true,
+			..Default::default()
+		};
+		let run = |input: &str| {
+			read_accounts(&mut HashMap::new(), &mut Cursor::new(input), &conf)
+		};
+
+		match run(include_str!("../test_samples/truncated/in.csv")) {
+			Err(ProcessError::Truncated(6)) => (),
+			res => panic!("expected truncation error: {:?}", res),
+		}
+		let (input, _) = load_samples!("simple");
+		run(input).unwrap();
+	}
+
+	// Applied and rejected counts by transaction type
+	#[test]
+	fn tx_stats() {
+		let (input, _) = load_samples!("disputes");
+		let report = read_accounts(
+			&mut HashMap::new(),
+			&mut Cursor::new(input.to_owned() + "resolve, 2, 2,\n"),
+			&Default::default(),
+		)
+		.unwrap();
+
+		let stats = |read, applied, rejected, ignored| TxStats {
+			read,
+			applied,
+			rejected,
+			ignored,
+		};
+
+		// The withdrawal from the locked account of client 2 is ignored
+		assert_eq!(
+			report.tx_stats.into_iter().collect::<Vec<_>>(),
+			[
+				(TxType::Deposit, stats(4, 4, 0, 0)),
+				(TxType::Withdrawal, stats(2, 1, 0, 1)),
+				(TxType::Dispute, stats(3, 3, 0, 0)),
+				(TxType::Resolve, stats(2, 1, 1, 0)),
+				(TxType::Chargeback, stats(1, 1, 0, 0)),
+			]
+		);
+	}
+
+	// Disputes of more than the available funds rejected instead of pushing
+	// them below zero
+	#[test]
+	fn dispute_shortfall_reject() {
+		for (dispute_shortfall, expected) in [
+			(DisputeShortfall::AllowNegative, Ok((-15_000, 20_000))),
+			(
+				DisputeShortfall::Reject,
+				Err(RejectReason::InsufficientAvailableForDispute),
+			),
+		] {
+			let conf = Config {
+				dispute_shortfall,
+				..Default::default()
+			};
+			let mut acc = Account::default();
+			for tx in [
+				Transaction::Deposit { tx: 1, amount: 2.0 },
+				Transaction::Withdrawal { tx: 2, amount: 1.5 },
+			] {
+				acc.apply_with(&tx, &conf).unwrap();
+			}
+			let res = acc
+				.apply_with(&Transaction::Dispute { tx: 1 }, &conf)
+				.map(|_| (acc.available, acc.held));
+			assert_eq!(res, expected);
+			if res.is_err() {
+				assert_eq!((acc.available, acc.held), (5_000, 0));
+			}
+		}
+	}
+
+	// Dispute of a deposit already partially withdrawn
+	#[test]
+	fn dispute_shortfall() {
+		for (dispute_shortfall, disputed) in [
+			(DisputeShortfall::AllowNegative, (-15_000, 20_000, 0)),
+			(DisputeShortfall::Clamp, (0, 5_000, 15_000)),
+		] {
+			let conf = Config {
+				dispute_shortfall,
+				..Default::default()
+			};
+			let mut acc = Account::default();
+			for tx in [
+				Transaction::Deposit { tx: 1, amount: 2.0 },
+				Transaction::Withdrawal { tx: 2, amount: 1.5 },
+				Transaction::Dispute { tx: 1 },
+			] {
+				acc.apply_with(&tx, &conf).unwrap();
+			}
+			assert_eq!((acc.available, acc.held, acc.shortfall), disputed);
+
+			acc.apply_with(
+				&Transaction::Resolve {
+					tx: 1,
+					amount: None,
+				},
+				&conf,
+			)
+			.unwrap();
+			assert_eq!((acc.available, acc.held, acc.shortfall), (5_000, 0, 0));
+
+			// Shortfall stays owed after a chargeback
+			for tx in [
+				Transaction::Dispute { tx: 1 },
+				Transaction::Chargeback {
+					tx: 1,
+					amount: None,
+				},
+			] {
+				acc.apply_with(&tx, &conf).unwrap();
+			}
+			assert_eq!(
+				(acc.available, acc.held, acc.shortfall),
+				(disputed.0, 0, disputed.2)
+			);
+		}
+	}
+
+	// Scripted session echoing the affected account after each row
+	#[test]
+	fn repl() {
+		let mut res = Vec::new();
+		super::repl(
+			&mut Cursor::new(
+				"deposit, 1, 1, 2.0
+				withdrawal, 1, 2, 0.5
+				deposit, 2
+
+				dispute, 1, 1
+				resolve, 2, 3,
+				chargeback, 1, 1,
+				resolve, 1, 1,",
+			),
+			&mut res,
+			&Default::default(),
+		)
+		.unwrap();
+
+		let res = std::str::from_utf8(&res).unwrap();
+		let (session, summary) = res.split_at(res.rfind("client").unwrap());
+		assert_eq!(
+			session.lines().collect::<Vec<_>>(),
+			[
+				"client,available,held,total,locked",
+				"1,2.0000,0.0000,2.0000,false",
+				"1,1.5000,0.0000,1.5000,false",
+				"line 3: field 2: cannot parse integer from empty string",
+				"1,-0.5000,2.0000,1.5000,false",
+				"2,0.0000,0.0000,0.0000,false",
+				"1,-0.5000,0.0000,-0.5000,true",
+				"line 8: resolve of tx 1 for client 1 rejected: \
+				resolve_after_chargeback",
+				"1,-0.5000,0.0000,-0.5000,true",
+			]
+		);
+		compare_output(
+			summary.as_bytes(),
+			"client,available,held,total,locked
+			1,-0.5000,0.0000,-0.5000,true
+			2,0.0000,0.0000,0.0000,false",
+		);
+	}
+
+	// Only the rows in the window are processed
+	#[test]
+	fn skip_take() {
+		let mut accounts = HashMap::new();
+		let report = read_accounts(
+			&mut accounts,
+			&mut Cursor::new(
+				"type, client, tx, amount
+				deposit, 1, 1, 1.0
+				deposit, 2, 2, 2.0
+				deposit, 1, 3, 3.0
+				dispute, 2, 2,
+				deposit, 2, 4, 4.0",
+			),
+			&Config {
+				skip_rows: 2,
+				take_rows: Some(2),
+				detect_truncation: true,
+				..Default::default()
+			},
+		)
+		.unwrap();
+		assert!(report.rejections.is_empty());
+		assert_eq!(accounts.len(), 2);
+		assert_eq!((accounts[&1].available, accounts[&1].held), (30_000, 0));
+		assert_eq!((accounts[&2].available, accounts[&2].held), (0, 0));
+	}
+
+	// Lines over the length limit are unparsable rows
+	#[test]
+	fn max_line_bytes() {
+		let input = format!(
+			"type, client, tx, amount
+			deposit, 1, 1, 1.0
+			deposit, 1, 2, 1.{}
+			deposit, 1, 3, 2.0
+			withdrawal, 1, 4,",
+			"0".repeat(100)
+		);
+		let read = |collect_errors| {
+			let mut accounts = HashMap::new();
+			let report = read_accounts(
+				&mut accounts,
+				&mut Cursor::new(&input),
+				&Config {
+					max_line_bytes: Some(64),
+					collect_errors,
+					..Default::default()
+				},
+			)?;
+			Ok::<_, ProcessError>((accounts, report))
+		};
+		let expected = ParseError {
+			line: 3,
+			message: "line exceeds 64 bytes".into(),
+		};
+
+		match read(false) {
+			Err(ProcessError::Parse(e)) => assert_eq!(e, expected),
+			res => panic!("unexpected result: {:?}", res.map(|_| ())),
+		}
+
+		let (accounts, report) = read(true).unwrap();
+		assert_eq!(report.parse_errors, [expected]);
+		assert_eq!(accounts[&1].available, 30_000);
+		assert_eq!(report.rejections[0].line, 5);
+	}
+
+	// Compressed output decompresses to the plain output
+	#[test]
+	fn gzip_output() {
+		let (input, _) = load_samples!("disputes");
+		let write = |gzip_output| {
+			let conf = Config {
+				gzip_output,
+				..Default::default()
+			};
+			let mut accounts = HashMap::new();
+			read_accounts(&mut accounts, &mut Cursor::new(input), &conf)
+				.unwrap();
+			let mut w = Vec::new();
+			write_accounts(&mut w, accounts, &conf).unwrap();
+			w
+		};
+
+		let mut decompressed = Vec::new();
+		flate2::read::GzDecoder::new(write(true).as_slice())
+			.read_to_end(&mut decompressed)
+			.unwrap();
+		compare_output(
+			&decompressed,
+			std::str::from_utf8(&write(false)).unwrap(),
+		);
+	}
+
+	// Amounts without a decimal point are whole major units
+	#[test]
+	fn integer_amounts() {
+		for conf in [
+			Config::default(),
+			Config {
+				amount_prefix: Some("$".into()),
+				decimal_separator: Some(','),
+				..Default::default()
+			},
+		] {
+			let mut accounts = HashMap::new();
+			read_accounts(
+				&mut accounts,
+				&mut Cursor::new(
+					"type, client, tx, amount
+					deposit, 1, 1, 5
+					deposit, 2, 2, 0
+					deposit, 3, 3, 100",
+				),
+				&conf,
+			)
+			.unwrap();
+			for (client, minor) in [(1, 50_000), (2, 0), (3, 1_000_000)] {
+				assert_eq!(accounts[&client].available, minor);
+			}
+		}
+	}
+
+	// Amounts beyond floating point precision are written exactly
+	#[test]
+	fn exact_output() {
+		// Float conversion is off by several minor units
+		assert_eq!(
+			format!("{:.4}", i64::MAX as f64 / 10_000.0),
+			"922337203685477.6250"
+		);
+		let conf = Config::default();
+		assert_eq!(format_amount(&conf, i64::MAX), "922337203685477.5807");
+		assert_eq!(format_amount(&conf, -5), "-0.0005");
+
+		// Rounded half to even beyond 4 decimals
+		let conf = Config {
+			units_per_major: NonZeroU32::new(1_000_000),
+			..Default::default()
+		};
+		for (minor, expected) in [
+			(15, "0.0000"),
+			(50, "0.0000"),
+			(150, "0.0002"),
+			(250, "0.0002"),
+			(251, "0.0003"),
+			(-150, "-0.0002"),
+		] {
+			assert_eq!(format_amount(&conf, minor), expected);
+		}
+	}
+
+	// Currency symbols are stripped from input amounts
+	#[test]
+	fn amount_prefix() {
+		for (prefix, separator, amount) in
+			[("$", None, "$5.0000"), ("€", Some(','), "\"€5,0000\"")]
+		{
+			let mut accounts = HashMap::new();
+			read_accounts(
+				&mut accounts,
+				&mut Cursor::new(format!(
+					"type, client, tx, amount
+					deposit, 1, 1,{}
+					deposit, 1, 2, 1.5",
+					amount
+				)),
+				&Config {
+					amount_prefix: Some(prefix.into()),
+					decimal_separator: separator,
+					..Default::default()
+				},
+			)
+			.unwrap();
+			assert_eq!(accounts[&1].available, 65_000);
+		}
+	}
+
+	// Comma decimal separator with a semicolon delimiter and a comma one
+	#[test]
+	fn decimal_separator() {
+		let (input, _) = load_samples!("simple");
+		for (delimiter, expected) in [
+			(
+				b';',
+				"client;available;held;total;locked
+				1;1,5000;0,0000;1,5000;false
+				2;2,0000;0,0000;2,0000;false",
+			),
+			(
+				b',',
+				"client,available,held,total,locked
+				1,\"1,5000\",\"0,0000\",\"1,5000\",false
+				2,\"2,0000\",\"0,0000\",\"2,0000\",false",
+			),
+		] {
+			compare_with(
+				input,
+				expected,
+				&Config {
+					decimal_separator: Some(','),
+					output_delimiter: Some(delimiter),
+					..Default::default()
+				},
+			);
+		}
+	}
+
+	// Accounts written in order of first appearance in the input
+	#[test]
+	fn sort_first_seen() {
+		let mut res = Vec::new();
+		process(
+			&mut res,
+			&mut Cursor::new(
+				"type, client, tx, amount
+				deposit, 7, 1, 1.0
+				deposit, 3, 2, 1.0
+				deposit, 7, 3, 1.0
+				deposit, 5, 4, 1.0
+				deposit, 1, 5, 1.0",
+			),
+			&Config {
+				sort_first_seen: true,
+				..Default::default()
+			},
+		)
+		.unwrap();
+
+		assert_eq!(
+			std::str::from_utf8(&res)
+				.unwrap()
+				.lines()
+				.skip(1)
+				.map(|l| l.split(',').next().unwrap())
+				.collect::<Vec<_>>(),
+			["7", "3", "5", "1"]
+		);
+	}
+
+	// Input pushed in chunks split at arbitrary byte boundaries
+	#[test]
+	fn ingest() {
+		let (input, _) = load_samples!("disputes");
+		let mut expected = HashMap::new();
+		read_accounts(
+			&mut expected,
+			&mut Cursor::new(input),
+			&Default::default(),
+		)
+		.unwrap();
+
+		let conf = Config::default();
+		for size in 1..=input.len() {
+			let mut ingest = Ingest::new(HashMap::new(), &conf);
+			for chunk in input.as_bytes().chunks(size) {
+				ingest.push(chunk).unwrap();
+			}
+			let (accounts, report) = ingest.finish().unwrap();
+			assert_eq!(accounts, expected, "chunk size {}", size);
+			assert!(report.rejections.is_empty());
+		}
+	}
+
+	// Dispute resolved automatically after the configured number of rows
+	#[test]
+	fn auto_resolve() {
+		let run = |input: &str| {
+			let mut accounts = HashMap::new();
+			read_accounts(
+				&mut accounts,
+				&mut Cursor::new(input),
+				&Config {
+					auto_resolve: Some(2),
+					..Default::default()
+				},
+			)
+			.unwrap();
+			balances(&accounts[&1])
+		};
+
+		const INPUT: &str = "type, client, tx, amount
+			deposit, 1, 1, 2.0
+			dispute, 1, 1,
+			deposit, 2, 2, 1.0";
+		assert_eq!(run(INPUT), (0, 20_000, false));
+		assert_eq!(
+			run(&(INPUT.to_owned() + "\ndeposit, 2, 3, 1.0")),
+			(20_000, 0, false)
+		);
+
+		// Reported like any other applied transaction
+		let input = INPUT.to_owned() + "\ndeposit, 2, 3, 1.0";
+		let conf = Config {
+			auto_resolve: Some(2),
+			..Default::default()
+		};
+		let events = super::process_with_events(input.as_bytes(), &conf)
+			.collect::<Result<Vec<_>, _>>()
+			.unwrap();
+		assert_eq!(
+			events.last(),
+			Some(&TxEvent {
+				line: 5,
+				typ: TxType::Resolve,
+				client: 1,
+				tx: 1,
+				available_delta: 20_000,
+				held_delta: -20_000,
+				available: 20_000,
+				held: 0,
+				locked: false,
+			})
+		);
+	}
+
+	// Adversarial inputs either process or fail with an error, but never
+	// panic
+	#[test]
+	fn adversarial() {
+		let long = format!("deposit, 1, 2, 1.{}", "0".repeat(1 << 20));
+		let huge_deposits = "deposit, 1, 1, 1e300\n".repeat(2);
+		let overflowing_withdrawals = "withdrawal, 1, 1, -9e14\n".repeat(2);
+		for (name, rows) in [
+			("max f64", "deposit, 1, 1, 1.7976931348623157e308"),
+			("min f64", "deposit, 1, 1, -1.7976931348623157e308"),
+			("subnormal", "deposit, 1, 1, 4.9e-324"),
+			("infinity", "deposit, 1, 1, inf"),
+			("NaN", "deposit, 1, 1, NaN"),
+			("huge deposits", &huge_deposits),
+			("overflowing withdrawals", &overflowing_withdrawals),
+			(
+				"disputed huge deposits",
+				"deposit, 1, 1, 4e14
+				deposit, 1, 2, 4e14
+				withdrawal, 1, 3, 4e14
+				withdrawal, 1, 4, 4e14
+				dispute, 1, 1,
+				dispute, 1, 2,
+				chargeback, 1, 1, -1e300",
+			),
+			(
+				"resolve onto huge balance",
+				"deposit,1,1,900000000000000
+				dispute,1,1,
+				deposit,1,2,900000000000000
+				resolve,1,1,",
+			),
+			(
+				"partial resolve onto huge balance",
+				"deposit,1,1,900000000000000
+				dispute,1,1,
+				deposit,1,2,900000000000000
+				resolve,1,1,1",
+			),
+			(
+				"chargeback leftover onto huge balance",
+				"deposit,1,1,900000000000000
+				dispute,1,1,
+				deposit,1,2,900000000000000
+				chargeback,1,1,1",
+			),
+			("very long line", &long),
+			("embedded nulls", "deposit, 1\0, 1, 1.0\0"),
+			("null row", "\0\0\0\0"),
+			("client overflow", "deposit, 65536, 1, 1.0"),
+			("tx overflow", "deposit, 1, 4294967296, 1.0"),
+			("missing fields", "deposit"),
+			("extra fields", "deposit, 1, 1, 1.0, 1.0"),
+			("replacement character", "deposit, 1, 1, \u{fffd}"),
+		] {
+			let input = format!("type, client, tx, amount\n{}", rows);
+			for conf in [
+				Config::default(),
+				Config {
+					collect_errors: true,
+					checksum: true,
+					dispute_window: Some(1),
+					dispute_withdrawals: true,
+					..Default::default()
+				},
+				Config {
+					partial_chargeback: PartialChargeback::Resolve,
+					..Default::default()
+				},
+				Config {
+					on_overflow: OverflowPolicy::ClampAndReport,
+					partial_chargeback: PartialChargeback::Resolve,
+					..Default::default()
+				},
+				Config {
+					on_overflow: OverflowPolicy::ClampAndReport,
+					accounting: Accounting::Annotate,
+					..Default::default()
+				},
+			] {
+				// Any result is fine, as long as there is no panic
+				let res =
+					process(&mut Vec::new(), &mut Cursor::new(&input), &conf);
+				if let Err(e) = res {
+					assert!(!e.to_string().is_empty(), "{}", name);
+				}
+			}
+		}
+
+		// Invalid UTF-8
+		process(
+			&mut Vec::new(),
+			&mut Cursor::new(b"type, client, tx, amount\n\xff\xfe, 1, 1, 1.0"),
+			&Default::default(),
+		)
+		.unwrap_err();
+	}
+
+	// Input fields quoted with a custom quote character
+	#[test]
+	fn custom_quote() {
+		struct DecimalComma;
+
+		impl AmountParser for DecimalComma {
+			fn parse(&self, s: &str) -> Result<f64, String> {
+				DecimalAmountParser.parse(&s.replace(',', "."))
+			}
+		}
+
+		compare_with(
+			"type,client,tx,amount
+			deposit,1,1,'1,5'
+			withdrawal,1,2,'0,25'",
+			"client,available,held,total,locked
+			1,1.2500,0.0000,1.2500,false",
+			&Config {
+				quote: Some(b'\''),
+				amount_parser: Some(Box::new(DecimalComma)),
+				..Default::default()
+			},
+		);
+	}
+
+	// Output ends with exactly one line terminator or none
+	#[test]
+	fn trailing_newline() {
+		let run = |no_trailing_newline| {
+			let mut res = vec![];
+			process(
+				&mut Cursor::new(&mut res),
+				&mut Cursor::new(
+					"type, client, tx, amount\ndeposit, 1, 1, 1.0\n",
+				),
+				&Config {
+					no_trailing_newline,
+					checksum: true,
+					..Default::default()
+				},
+			)
+			.unwrap();
+			String::from_utf8(res).unwrap()
+		};
+
+		let res = run(false);
+		assert!(res.ends_with("checksum,1.0000,1\n"));
+		assert!(!res.ends_with("\n\n"));
+		assert_eq!(
+			run(true),
+			"client,available,held,total,locked
+1,1.0000,0.0000,1.0000,false
+checksum,1.0000,1"
+		);
+	}
+
+	// Rows with a wrong field count are collected instead of aborting
+	// processing
+	#[test]
+	fn collect_field_count_errors() {
+		const INPUT: &str = "type, client, tx, amount
+			deposit, 1, 1, 1.0
+			deposit, 1, 2
+			deposit, 2, 3, 3.0, 1
+			withdrawal, 1, 4, 0.5";
+
+		assert!(matches!(
+			process(
+				&mut io::sink(),
+				&mut Cursor::new(INPUT),
+				&Default::default()
+			),
+			Err(ProcessError::Csv(_))
+		));
+
+		let mut res = vec![];
+		let report = process(
+			&mut Cursor::new(&mut res),
+			&mut Cursor::new(INPUT),
+			&Config {
+				collect_errors: true,
+				..Default::default()
+			},
+		)
+		.unwrap();
+		assert_eq!(
+			report.parse_errors,
+			[
+				ParseError {
+					line: 3,
+					message: "expected 4 fields, found 3".into(),
+				},
+				ParseError {
+					line: 4,
+					message: "expected 4 fields, found 5".into(),
+				},
+			]
+		);
+		compare_output(
+			&res,
+			"client,available,held,total,locked
+			1,0.5000,0.0000,0.5000,false",
+		);
+	}
+
+	// Custom amount parser handling accounting notation negatives
+	#[test]
+	fn custom_amount_parser() {
+		struct Parenthesized;
+
+		impl AmountParser for Parenthesized {
+			fn parse(&self, s: &str) -> Result<f64, String> {
+				match s.strip_prefix('(').and_then(|s| s.strip_suffix(')')) {
+					Some(s) => DecimalAmountParser.parse(s).map(|a| -a),
+					None => DecimalAmountParser.parse(s),
+				}
+			}
+		}
+
+		compare_with(
+			"type, client, tx, amount
+			deposit, 1, 1, 10.0
+			deposit, 1, 2, (5.0000)",
+			"client,available,held,total,locked
+			1,5.0000,0.0000,5.0000,false",
+			&Config {
+				amount_parser: Some(Box::new(Parenthesized)),
+				..Default::default()
+			},
+		);
+	}
+
+	// Locked and active accounts written to separate sinks
+	#[test]
+	fn split_by_lock() {
+		let (input, _) = load_samples!("disputes");
+		let mut accounts = HashMap::new();
+		read_accounts(
+			&mut accounts,
+			&mut Cursor::new(input),
+			&Default::default(),
+		)
+		.unwrap();
+
+		let (mut locked, mut active) = (Vec::new(), Vec::new());
+		write_accounts_split(
+			&mut locked,
+			&mut active,
+			accounts,
+			&Default::default(),
+		)
+		.unwrap();
+		compare_output(
+			&locked,
+			"client,available,held,total,locked
+			2,5.0000,0.0000,5.0000,true",
+		);
+		compare_output(
+			&active,
+			"client,available,held,total,locked
+			1,0.5000,1.0000,1.5000,false",
+		);
+	}
+
+	// Single transactions applied to a known account state
+	#[test]
+	fn apply() {
+		let mut acc = Account::default();
+		acc.apply(&Transaction::Deposit { tx: 1, amount: 2.0 })
+			.unwrap();
+		assert_eq!(balances(&acc), (20_000, 0, false));
+		assert_eq!(acc.deposits[&1], Deposit::new(20_000));
+
+		acc.apply(&Transaction::Withdrawal { tx: 2, amount: 0.5 })
+			.unwrap();
+		assert_eq!(balances(&acc), (15_000, 0, false));
+
+		// Insufficient funds
+		acc.apply(&Transaction::Withdrawal { tx: 3, amount: 5.0 })
+			.unwrap();
+		assert_eq!(balances(&acc), (15_000, 0, false));
+
+		acc.apply(&Transaction::Dispute { tx: 1 }).unwrap();
+		assert_eq!(balances(&acc), (-5_000, 20_000, false));
+		assert_eq!(acc.deposits[&1].dispute_state, DisputeState::Initiated);
+
+		acc.apply(&Transaction::Resolve {
+			tx: 1,
+			amount: None,
+		})
+		.unwrap();
+		assert_eq!(balances(&acc), (15_000, 0, false));
+		assert_eq!(acc.deposits[&1].dispute_state, DisputeState::NotInitiated);
+
+		acc.apply(&Transaction::Dispute { tx: 1 }).unwrap();
+		acc.apply(&Transaction::Chargeback {
+			tx: 1,
+			amount: None,
+		})
+		.unwrap();
+		assert_eq!(balances(&acc), (-5_000, 0, true));
+		assert_eq!(acc.locked_reason, Some(1));
+		assert_eq!(
+			acc.apply(&Transaction::Resolve {
+				tx: 1,
+				amount: None,
+			}),
+			Err(RejectReason::ResolveAfterChargeback)
+		);
+
+		// Locked accounts still accept deposits, but not withdrawals
+		acc.apply(&Transaction::Deposit { tx: 4, amount: 1.0 })
+			.unwrap();
+		acc.apply(&Transaction::Withdrawal { tx: 5, amount: 0.5 })
+			.unwrap();
+		assert_eq!(balances(&acc), (5_000, 0, true));
+
+		// Not applied under the default policy
+		acc.apply(&Transaction::Unknown(UnknownTx {
+			line: 1,
+			typ: "bonus",
+			client: 1,
+			tx: 6,
+			amount: Some(1.0),
+		}))
+		.unwrap();
+		assert_eq!(balances(&acc), (5_000, 0, true));
+	}
+
+	// Repeated dispute and resolve cycles leave no funds held
+	#[test]
+	fn dispute_resolve_cycles() {
+		let mut acc = Account::default();
+		acc.apply(&Transaction::Deposit {
+			tx: 1,
+			amount: 1.2345,
+		})
+		.unwrap();
+		for _ in 0..100 {
+			acc.apply(&Transaction::Dispute { tx: 1 }).unwrap();
+			assert_eq!(balances(&acc), (0, 12_345, false));
+			acc.apply(&Transaction::Resolve {
+				tx: 1,
+				amount: None,
+			})
+			.unwrap();
+		}
+		assert_eq!(balances(&acc), (12_345, 0, false));
+		assert_eq!(acc.deposits[&1], Deposit::new(12_345));
+	}
+
+	// Handling of unknown transaction types under each policy
+	#[test]
+	fn unknown_tx() {
+		const INPUT: &str = "type, client, tx, amount
+			deposit, 1, 1, 1.0
+			bonus, 1, 2, 0.5";
+		let run = |unknown_tx| {
+			let mut accounts = HashMap::new();
+			read_accounts(
+				&mut accounts,
+				&mut Cursor::new(INPUT),
+				&Config {
+					unknown_tx,
+					..Default::default()
+				},
+			)
+			.map(|report| (accounts.remove(&1).unwrap(), report))
+		};
+
+		match run(UnknownTxPolicy::Fail) {
+			Err(ProcessError::Parse(e)) => assert_eq!(
+				e,
+				ParseError {
+					line: 3,
+					message: "unknown transaction type: bonus".into(),
+				}
+			),
+			res => panic!("expected parse error: {:?}", res),
+		}
+
+		let (acc, report) = run(UnknownTxPolicy::Ignore).unwrap();
+		assert_eq!(balances(&acc), (10_000, 0, false));
+		assert!(report.rejections.is_empty());
+
+		let (acc, report) = run(UnknownTxPolicy::Reject).unwrap();
+		assert_eq!(balances(&acc), (10_000, 0, false));
+		assert_eq!(
+			report
+				.rejections
+				.iter()
+				.map(|r| (r.line, r.typ, r.reason))
+				.collect::<Vec<_>>(),
+			[(3, TxType::Unknown, RejectReason::UnknownType)]
+		);
+
+		let (acc, report) =
+			run(UnknownTxPolicy::Handler(Box::new(|tx, acc| {
+				match (tx.typ, tx.amount) {
+					("bonus", Some(amount)) => {
+						acc.available += to_minor(&Default::default(), amount);
+						Ok(())
+					}
+					_ => Err(RejectReason::UnknownType),
+				}
+			})))
+			.unwrap();
+		assert_eq!(balances(&acc), (15_000, 0, false));
+		assert!(report.rejections.is_empty());
+	}
+
+	// Header-less input with positional columns
+	#[test]
+	fn no_header() {
+		let (input, expected) = load_samples!("no_header");
+		compare_with(
+			input,
+			expected,
+			&Config {
+				no_header: true,
+				..Default::default()
+			},
+		);
+	}
+
+	// Merging accounts of disjoint client shards
+	#[test]
+	fn merge_disjoint() {
+		let merged = merge_accounts(
+			shard("deposit, 1, 1, 1.0\nwithdrawal, 1, 2, 0.5"),
+			shard("deposit, 2, 3, 2.0\ndispute, 2, 3,"),
+		);
+		assert_eq!(merged.len(), 2);
+		assert_eq!(balances(&merged[&1]), (5_000, 0, false));
+		assert_eq!(balances(&merged[&2]), (0, 20_000, false));
+	}
+
+	// Merging accounts of shards that share a client
+	#[test]
+	fn merge_overlapping() {
+		let merged = merge_accounts(
+			shard("deposit, 1, 1, 1.0\ndeposit, 2, 2, 3.0"),
+			shard(
+				"deposit, 1, 3, 2.0
+				dispute, 1, 3,
+				deposit, 1, 4, 1.0
+				dispute, 1, 4,
+				chargeback, 1, 4,
+				deposit, 1, 1, 5.0",
+			),
+		);
+		assert_eq!(merged.len(), 2);
+		assert_eq!(balances(&merged[&2]), (30_000, 0, false));
+
+		let acc = &merged[&1];
+		assert_eq!(balances(acc), (60_000, 20_000, true));
+		assert_eq!(acc.deposits.len(), 3);
+		assert_eq!(acc.deposits[&3].dispute_state, DisputeState::Initiated);
+		assert_eq!(acc.deposits[&4].dispute_state, DisputeState::ChargedBack);
+
+		// Duplicate transaction ID keeps the record from the first map
+		assert_eq!(acc.deposits[&1].amount, 10_000);
+	}
+
+	/// Process transaction rows without the header into an account map
+	fn shard(rows: &str) -> HashMap<u16, Account> {
+		let mut accounts = HashMap::new();
+		read_accounts(
+			&mut accounts,
+			&mut Cursor::new(format!("type, client, tx, amount\n{}", rows)),
+			&Default::default(),
+		)
+		.unwrap();
+		accounts
+	}
+
+	/// Return available and held minor units and lock status of an account
+	fn balances(acc: &Account) -> (i64, i64, bool) {
+		(acc.available, acc.held, acc.locked)
+	}
+
+	/// Assert `input` produces only the `expected` rejection in lenient mode
+	/// and aborts with it in strict mode
+	fn assert_rejected(input: &str, mut conf: Config, expected: Rejection) {
+		let report =
+			process(&mut io::sink(), &mut Cursor::new(input), &conf).unwrap();
+		assert_eq!(report.rejections, vec![expected.clone()]);
+
+		conf.strict = true;
+		match process(&mut io::sink(), &mut Cursor::new(input), &conf) {
+			Err(ProcessError::InvalidRow(rej)) => assert_eq!(rej, expected),
+			res => panic!("expected invalid row error: {:?}", res),
+		}
+	}
+
+	fn compare(input: &str, expected: &str) {
+		compare_with(input, expected, &Default::default());
+	}
+
+	fn compare_with(input: &str, expected: &str, conf: &Config) {
+		let mut res = vec![];
+		process(&mut Cursor::new(&mut res), &mut Cursor::new(input), conf)
+			.unwrap();
+		compare_output(&res, expected);
+	}
+
+	/// Compare output CSV to the expected one regardless of row order and
+	/// leading whitespace
+	fn compare_output(res: &[u8], expected: &str) {
+		fn sort(csv: &str) -> String {
+			let i = csv.find('\n').unwrap();
+			let mut lines =
+				csv[i + 1..].lines().map(str::trim).collect::<Vec<_>>();
+			lines.sort();
+			lines.iter().fold(csv[..i].to_owned(), |mut w, line| {
+				w.push('\n');
+				w += line;
+				w
+			})
+		}
+
+		assert_eq!(sort(expected), sort(std::str::from_utf8(res).unwrap()));
+	}
+}
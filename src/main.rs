@@ -1,22 +1,84 @@
-use serde::{Deserialize, Serialize};
+use rust_test::{
+	load_state_with, process_progressive, read_accounts_traced,
+	read_dir_accounts, read_file_accounts, read_files_parallel,
+	read_zip_accounts, write_accounts, write_accounts_split, write_ledger,
+	write_rejections, Account, ClientFilter, Config, ProcessError, Report,
+};
 use std::{
 	collections::HashMap,
-	error::Error,
 	fs::File,
-	io::{stdout, BufReader, BufWriter, Read, Write},
+	io::{self, stdout, BufReader, BufWriter},
+	ops::RangeInclusive,
+	path::Path,
 };
 
 // TODO: go through PDF and assert everything is covered
 
+/// Parsed command line arguments
+struct Args {
+	/// Input file and directory paths, processed in order against the same
+	/// account state
+	paths: Vec<String>,
+
+	/// Name of the CSV file to process inside zip archive inputs
+	zip_entry: Option<String>,
+
+	/// Path to a binary account state snapshot to start processing from
+	state: Option<String>,
+
+	/// Directory to write locked and active accounts to separately instead of
+	/// stdout
+	split_by_lock: Option<String>,
+
+	/// Path to write rejected transactions to as CSV instead of stderr
+	reject_output: Option<String>,
+
+	/// Client to write the deposit ledger of instead of the account statuses
+	dump_ledger: Option<u16>,
+
+	/// Print transaction counts by type
+	tx_stats: bool,
+
+	/// Print each input row and its outcome, like the resulting account state
+	/// or the reason of its rejection. Only supports plain CSV file inputs.
+	trace: bool,
+
+	/// Apply transactions typed into stdin interactively
+	repl: bool,
+
+	/// Process CSV files concurrently and merge their accounts
+	parallel: bool,
+
+	/// Write accounts as soon as they are finalized by `finalize` rows
+	progressive: bool,
+
+	/// Processing options
+	conf: Config,
+}
+
 fn main() -> Result<(), String> {
-	let path = match std::env::args().nth(1) {
-		Some(p) => p,
-		None => {
-			return Err(
-				"file path required as the first and only parameter".into()
-			)
-		}
-	};
+	let Args {
+		paths,
+		zip_entry,
+		state,
+		split_by_lock,
+		reject_output,
+		dump_ledger,
+		tx_stats,
+		trace,
+		repl,
+		parallel,
+		progressive,
+		conf,
+	} = parse_args()?;
+	if repl {
+		return rust_test::repl(
+			&mut io::stdin().lock(),
+			&mut stdout().lock(),
+			&conf,
+		)
+		.map_err(|e| e.to_string());
+	}
 
 	// Buffer to reduce syscalls.
 	//
@@ -29,250 +91,446 @@ fn main() -> Result<(), String> {
 	// The process() function can be converted to run asynchronously on a
 	// multithreaded Tokio runtime, if this application is to be adapted
 	// for concurrent multiple request handling.
-	(|| {
-		process(
-			&mut BufWriter::new(stdout()),
-			&mut BufReader::new(File::open(path)?),
-		)
-	})()
-	.map_err(|e| e.to_string())
-}
-
-/// Process a CSV stream `r` and write the account status CSV to `w`
-fn process(
-	w: &mut impl Write,
-	r: &mut impl Read,
-) -> Result<(), Box<dyn Error>> {
-	let mut accounts = HashMap::<u16, Account>::with_capacity(64);
-
-	// Read input CSV rows
-	for res in csv::ReaderBuilder::new()
-		.trim(csv::Trim::All)
-		.from_reader(r)
-		.deserialize()
-	{
-		let row: InRow = res?;
-		let acc = accounts.entry(row.client).or_default();
+	//
+	// Multiple files of disjoint clients can be processed concurrently with
+	// --parallel.
+	let report = (|| {
+		if progressive {
+			return process_progressive(
+				&mut BufWriter::new(stdout()),
+				&mut BufReader::new(File::open(&paths[0])?),
+				&conf,
+			);
+		}
 
-		match (&row.typ, &row.amount) {
-			(TxType::Deposit, Some(amount)) => {
-				let amount = to_minor(*amount);
-				acc.available += amount;
-				acc.deposits.insert(
-					row.tx,
-					Deposit {
-						dispute_state: DisputeState::NotInitiated,
-						amount,
-					},
-				);
-			}
-			(TxType::Withdrawal, Some(amount)) => {
-				// The task definition did not specify what exactly locking an
-				// account entails.The term "freeze" was also used to describe
-				// locking, so I went with the Investopedia  definition of
-				// allowing deposits, but not withdrawals.
-				// Further disputes and chargebacks are also allowed on locked
-				// accounts, based on my understanding of what the business
-				// logic should be in those cases.
-				if !acc.locked {
-					let amount = to_minor(*amount);
-					if acc.available >= amount {
-						acc.available -= amount;
-					}
-				}
-			}
-			(TxType::Dispute, _) => {
-				if let Some(d) = acc.deposits.get_mut(&row.tx) {
-					if matches!(d.dispute_state, DisputeState::NotInitiated) {
-						d.dispute_state = DisputeState::Initiated;
-						acc.available -= d.amount;
-						acc.held += d.amount;
-					}
-				}
-			}
-			(TxType::Resolve, _) => {
-				if let Some(d) = acc.deposits.get_mut(&row.tx) {
-					if matches!(d.dispute_state, DisputeState::Initiated) {
-						// Enable starting another dispute
-						d.dispute_state = DisputeState::NotInitiated;
+		let (accounts, report) = if parallel {
+			read_files_parallel(&paths, &conf)?
+		} else {
+			read_sequential(
+				&paths,
+				state.as_deref(),
+				zip_entry.as_deref(),
+				trace,
+				&conf,
+			)?
+		};
+
+		if let Some(client) = dump_ledger {
+			write_ledger(
+				&mut BufWriter::new(stdout()),
+				accounts.get(&client).unwrap_or(&Account::default()),
+				&conf,
+			)?;
+			return Ok(report);
+		}
+		match &split_by_lock {
+			Some(dir) => {
+				let dir = Path::new(dir);
+				write_accounts_split(
+					&mut BufWriter::new(File::create(dir.join("locked.csv"))?),
+					&mut BufWriter::new(File::create(dir.join("active.csv"))?),
+					accounts,
+					&conf,
+				)?
+			}
+			None => {
+				write_accounts(&mut BufWriter::new(stdout()), accounts, &conf)?
+			}
+		}
+		Ok::<_, ProcessError>(report)
+	})()
+	.map_err(|e| e.to_string())?;
+
+	// Rejected transactions do not abort processing outside of strict mode,
+	// but still need to be surfaced
+	if let Some(p) = &reject_output {
+		(|| {
+			write_rejections(
+				&mut BufWriter::new(File::create(p)?),
+				&report.rejections,
+			)
+		})()
+		.map_err(|e| e.to_string())?;
+	} else {
+		for rej in &report.rejections {
+			eprintln!("{}", rej);
+		}
+	}
+	for rej in &report.skipped {
+		eprintln!("{}", rej);
+	}
+	for w in &report.precision_warnings {
+		eprintln!("{}", w);
+	}
+	for a in &report.adjustments {
+		eprintln!("{}", a);
+	}
+	for c in &report.clamped {
+		eprintln!(
+			"line {}: {} of tx {} for client {} applied with clamped balances",
+			c.line,
+			c.typ.as_str(),
+			c.tx,
+			c.client
+		);
+	}
 
-						acc.available += d.amount;
-						acc.held -= d.amount;
-					}
-				}
-			}
-			(TxType::Chargeback, _) => {
-				if let Some(d) = acc.deposits.get_mut(&row.tx) {
-					if matches!(d.dispute_state, DisputeState::Initiated) {
-						d.dispute_state = DisputeState::ChargedBack;
-						acc.held -= d.amount;
-						acc.locked = true;
-					}
-				}
-			}
-			// Ignoring invalid cases to match behaviour of all other
-			// validations
-			_ => (),
+	if tx_stats {
+		for (typ, s) in &report.tx_stats {
+			eprintln!(
+				"{}: read {}, applied {}, rejected {}, ignored {}",
+				typ.as_str(),
+				s.read,
+				s.applied,
+				s.rejected,
+				s.ignored
+			);
 		}
 	}
 
-	// Dump output as CSV
-	let mut w = csv::Writer::from_writer(w);
-	for (cl, acc) in accounts {
-		w.serialize(OutRow {
-			client: cl,
-			available: to_major(acc.available),
-			held: to_major(acc.held),
-			total: to_major(acc.available + acc.held),
-			locked: acc.locked,
-		})?;
+	for e in &report.parse_errors {
+		eprintln!("{}", e);
+	}
+	if !report.parse_errors.is_empty() {
+		return Err(format!(
+			"{} rows could not be parsed",
+			report.parse_errors.len()
+		));
 	}
 
 	Ok(())
 }
 
-/// A row of the input CSV file
-#[derive(Deserialize)]
-struct InRow {
-	/// Transaction type
-	#[serde(rename = "type")]
-	typ: TxType,
-
-	/// Client ID
-	client: u16,
-
-	/// Transaction ID
-	tx: u32,
-
-	/// Transaction amount in major currency units
-	amount: Option<f64>,
-}
-
-/// A row of the output CSV file
-#[derive(Serialize)]
-struct OutRow {
-	/// Client ID
-	client: u16,
-
-	/// Available amount in major currency units
-	available: String,
-
-	/// Held amount in major currency units
-	held: String,
-
-	/// Total amount in major currency units
-	total: String,
-
-	/// Account locked due to a chargeback. No more withdrawals are possible.
-	locked: bool,
-}
-
-/// Supported transactions types
-#[derive(Deserialize)]
-#[serde(rename_all = "lowercase")]
-enum TxType {
-	Deposit,
-	Withdrawal,
-	Dispute,
-	Resolve,
-	Chargeback,
-}
-
-// State of a possibly initiated dispute for a deposit transaction
-enum DisputeState {
-	NotInitiated,
-	Initiated,
-	ChargedBack,
-}
-
-/// Deposit transaction state and amount.
-/// Stored for dispute resolution purposes only.
-struct Deposit {
-	// State of a possibly initiated dispute for the transaction
-	dispute_state: DisputeState,
-
-	/// Transaction amount in minor units.
-	amount: i64,
-}
-
-/// Current state of a client's account
-#[derive(Default)]
-struct Account {
-	/// Account locked due to a chargeback. No more withdrawals are possible.
-	locked: bool,
-
-	/// Funds currently available for withdrawal in minor currency units
-	available: i64,
-
-	/// Funds currently held from withdrawal in minor currency units
-	held: i64,
-
-	/// Deposit transaction registry by transaction ID
-	deposits: HashMap<u32, Deposit>,
-}
+/// Process input `paths` in order against the same account state, starting
+/// from the snapshot at path `state`, if any
+fn read_sequential(
+	paths: &[String],
+	state: Option<&str>,
+	zip_entry: Option<&str>,
+	trace: bool,
+	conf: &Config,
+) -> Result<(HashMap<u16, Account>, Report), ProcessError> {
+	let mut accounts = match state {
+		Some(p) => load_state_with(&mut BufReader::new(File::open(p)?), conf)?,
+		None => HashMap::with_capacity(64),
+	};
 
-/// Convert amount in major currency units to minor units.
-///
-/// Done to avoid FP arithmetic errors.
-/// There are 10_000 minor in each major unit of currency.
-///
-/// If arbitrary precisions is desired, these can be switched to bignums later
-/// on. Not used at the moment, as ints are more efficient.
-fn to_minor(amount: f64) -> i64 {
-	(amount * 10_000_f64) as _
-}
+	let mut report = Report::default();
+	for path in paths {
+		if Path::new(path).is_dir() {
+			report.extend(read_dir_accounts(
+				&mut accounts,
+				Path::new(path),
+				conf,
+			)?);
+			continue;
+		}
 
-/// Convert amount in minor currency units to a major unit string of 4 decimal
-/// precision
-fn to_major(amount: i64) -> String {
-	format!("{:.4}", (amount as f64) / 10_000_f64)
+		let file = File::open(path)?;
+		report.extend(if zip_entry.is_some() || path.ends_with(".zip") {
+			read_zip_accounts(
+				&mut accounts,
+				BufReader::new(file),
+				zip_entry,
+				conf,
+			)?
+		} else if trace {
+			read_accounts_traced(
+				&mut accounts,
+				&mut BufReader::new(file),
+				conf,
+				|s| eprintln!("{}", s),
+			)?
+		} else {
+			read_file_accounts(&mut accounts, &file, conf)?
+		});
+	}
+	Ok((accounts, report))
 }
 
-#[cfg(test)]
-mod test {
-	use std::io::Cursor;
-
-	use crate::process;
-
-	/// Load input sample and expected output
-	macro_rules! load_samples {
-		($dir:literal) => {{
-			(
-				include_str!(concat!("../test_samples/", $dir, "/in.csv")),
-				include_str!(concat!("../test_samples/", $dir, "/out.csv")),
-			)
-		}};
+/// Parse command line arguments into the input file paths and processing
+/// options
+fn parse_args() -> Result<Args, String> {
+	let mut paths = Vec::new();
+	let mut zip_entry = None;
+	let mut state = None;
+	let mut split_by_lock = None;
+	let mut reject_output = None;
+	let mut dump_ledger = None;
+	let mut tx_stats = false;
+	let mut trace = false;
+	let mut parallel = false;
+	let mut progressive = false;
+	let mut repl = false;
+	let mut detect_scale = None;
+	let mut conf = Config::default();
+	let mut args = std::env::args().skip(1);
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--no-header" => conf.no_header = true,
+			"--strict" => conf.strict = true,
+			"--all-or-nothing" => conf.all_or_nothing = true,
+			"--collect-errors" => conf.collect_errors = true,
+			"--verify-tx-order" => conf.verify_tx_order = true,
+			"--validate-signs" => conf.validate_signs = true,
+			"--report-refused-withdrawals" => {
+				conf.report_refused_withdrawals = true
+			}
+			"--detect-withdrawal-disputes" => {
+				conf.detect_withdrawal_disputes = true
+			}
+			"--reject-undisputed-resolves" => {
+				conf.reject_undisputed_resolves = true
+			}
+			"--unknown-tx" => conf.unknown_tx = parse_value(&arg, args.next())?,
+			"--extended" => conf.extended_output = true,
+			"--no-lock-on-chargeback" => conf.no_lock_on_chargeback = true,
+			"--audit-columns" => conf.audit_columns = true,
+			"--checksum" => conf.checksum = true,
+			"--scale-header" => conf.scale_header = true,
+			"--grand-total" => conf.grand_total = true,
+			"--allow-admin" => conf.allow_admin = true,
+			"--mmap" => conf.mmap = true,
+			"--tx-stats" => tx_stats = true,
+			"--trace" => trace = true,
+			"--parallel" => parallel = true,
+			"--threads" => {
+				conf.max_threads = Some(parse_value(&arg, args.next())?)
+			}
+			"--progressive" => progressive = true,
+			"--sorted-by-client" => {
+				conf.sorted_by_client = true;
+				progressive = true;
+			}
+			"--repl" => repl = true,
+			"--detect-truncation" => conf.detect_truncation = true,
+			"--skip" => conf.skip_rows = parse_value(&arg, args.next())?,
+			"--take" => conf.take_rows = Some(parse_value(&arg, args.next())?),
+			"--client-width" => {
+				conf.client_width = Some(parse_value(&arg, args.next())?)
+			}
+			"--max-line-bytes" => {
+				conf.max_line_bytes = Some(parse_value(&arg, args.next())?)
+			}
+			"--minor-units" => conf.minor_units = true,
+			"--detect-scale" => {
+				detect_scale = Some(parse_value(&arg, args.next())?)
+			}
+			"--units-per-major" => {
+				conf.units_per_major = Some(parse_value(&arg, args.next())?)
+			}
+			"--sort-first-seen" => conf.sort_first_seen = true,
+			"--decimal-separator" => {
+				conf.decimal_separator = Some(parse_value(&arg, args.next())?)
+			}
+			"--delimiter" => {
+				conf.output_delimiter = Some(
+					parse_value::<char>(&arg, args.next())?
+						.try_into()
+						.map_err(|_| {
+							"delimiter must be a single-byte character"
+						})?,
+				)
+			}
+			"--quote" => {
+				conf.quote = Some(
+					parse_value::<char>(&arg, args.next())?
+						.try_into()
+						.map_err(|_| "quote must be a single-byte character")?,
+				)
+			}
+			"--dispute-withdrawals" => conf.dispute_withdrawals = true,
+			"--chargeback-opens-dispute" => {
+				conf.chargeback_opens_dispute = true
+			}
+			"--suppress-empty" => conf.suppress_empty = true,
+			"--suppress-inactive" => conf.suppress_inactive = true,
+			"--no-trailing-newline" => conf.no_trailing_newline = true,
+			"--gzip-output" => conf.gzip_output = true,
+			"--clients" => {
+				conf.clients =
+					ClientFilter::Allow(parse_list(&arg, args.next())?)
+			}
+			"--exclude-clients" => {
+				conf.clients =
+					ClientFilter::Deny(parse_list(&arg, args.next())?)
+			}
+			"--report-skipped" => conf.report_skipped = true,
+			"--reserved-tx" => {
+				conf.reserved_tx = Some(parse_range(&arg, args.next())?)
+			}
+			"--max-held" => {
+				conf.max_held = Some(parse_value(&arg, args.next())?)
+			}
+			"--precision-warning" => {
+				conf.precision_warning = Some(parse_value(&arg, args.next())?)
+			}
+			"--opening-balance" => {
+				conf.opening_balance = Some(parse_value(&arg, args.next())?)
+			}
+			"--max-amount" => {
+				conf.max_amount = Some(parse_value(&arg, args.next())?)
+			}
+			"--min-deposit" => {
+				conf.min_deposit = Some(parse_value(&arg, args.next())?)
+			}
+			"--min-residual" => {
+				conf.min_residual = Some(parse_value(&arg, args.next())?)
+			}
+			"--amount-prefix" => {
+				conf.amount_prefix = Some(parse_value(&arg, args.next())?)
+			}
+			"--currency" => {
+				conf.currency = Some(parse_value(&arg, args.next())?)
+			}
+			"--zip-entry" => zip_entry = Some(parse_value(&arg, args.next())?),
+			"--state" => state = Some(parse_value(&arg, args.next())?),
+			"--reject-output" => {
+				reject_output = Some(parse_value(&arg, args.next())?)
+			}
+			"--dump-ledger" => {
+				dump_ledger = Some(parse_value(&arg, args.next())?)
+			}
+			"--split-by-lock" => {
+				split_by_lock = Some(parse_value(&arg, args.next())?)
+			}
+			"--partial-chargeback" => {
+				conf.partial_chargeback = parse_value(&arg, args.next())?
+			}
+			"--charged-back" => {
+				conf.charged_back = parse_value(&arg, args.next())?
+			}
+			"--auto-resolve" => {
+				conf.auto_resolve = Some(parse_value(&arg, args.next())?)
+			}
+			"--dispute-grace" => {
+				conf.dispute_grace = Some(parse_value(&arg, args.next())?)
+			}
+			"--deposit-cache" => {
+				conf.deposit_cache = Some(parse_value(&arg, args.next())?)
+			}
+			"--dispute-window" => {
+				conf.dispute_window = Some(parse_value(&arg, args.next())?)
+			}
+			"--format" => conf.output_format = parse_value(&arg, args.next())?,
+			"--columns" => conf.columns = Some(parse_list(&arg, args.next())?),
+			"--accounting" => conf.accounting = parse_value(&arg, args.next())?,
+			"--state-check" => {
+				conf.state_check = parse_value(&arg, args.next())?
+			}
+			"--dispute-shortfall" => {
+				conf.dispute_shortfall = parse_value(&arg, args.next())?
+			}
+			"--bool-style" => conf.bool_style = parse_value(&arg, args.next())?,
+			"--client-zero" => {
+				conf.client_zero = parse_value(&arg, args.next())?
+			}
+			"--on-overflow" => {
+				conf.on_overflow = parse_value(&arg, args.next())?
+			}
+			_ if arg.starts_with("--") => {
+				return Err(format!("unknown option: {}", arg))
+			}
+			_ => paths.push(arg),
+		}
 	}
-
-	// Simple case of deposits and withdrawals
-	#[test]
-	fn simple() {
-		let (input, expected) = load_samples!("simple");
-		compare(input, expected);
+	if paths.is_empty() && !repl {
+		return Err("file path required as a parameter".into());
 	}
-
-	// Deposits, withdrawals and dispute resolution
-	#[test]
-	fn disputes() {
-		let (input, expected) = load_samples!("disputes");
-		compare(input, expected);
+	if parallel && (state.is_some() || zip_entry.is_some() || trace) {
+		return Err("--parallel only supports plain CSV file inputs".into());
+	}
+	if trace
+		&& (zip_entry.is_some()
+			|| paths
+				.iter()
+				.any(|p| p.ends_with(".zip") || Path::new(p).is_dir()))
+	{
+		return Err("--trace only supports plain CSV file inputs".into());
 	}
+	if conf.gzip_output && (progressive || repl) {
+		return Err(
+			"--gzip-output is not supported with --progressive and --repl"
+				.into(),
+		);
+	}
+	if progressive
+		&& (paths.len() != 1
+			|| state.is_some()
+			|| zip_entry.is_some()
+			|| split_by_lock.is_some()
+			|| dump_ledger.is_some()
+			|| parallel
+			|| trace)
+	{
+		return Err(
+			"--progressive only supports a single plain CSV file input \
+			and output to stdout"
+				.into(),
+		);
+	}
+	if let Some(rows) = detect_scale {
+		let path = match paths.as_slice() {
+			[path, ..]
+				if zip_entry.is_none()
+					&& !path.ends_with(".zip")
+					&& !Path::new(path).is_dir() =>
+			{
+				path
+			}
+			_ => {
+				return Err(
+					"--detect-scale only supports plain CSV file inputs".into(),
+				)
+			}
+		};
+		let file = File::open(path).map_err(|e| e.to_string())?;
+		conf.scale = rust_test::detect_scale(BufReader::new(file), rows, &conf);
+	}
+	Ok(Args {
+		paths,
+		zip_entry,
+		state,
+		split_by_lock,
+		reject_output,
+		dump_ledger,
+		tx_stats,
+		trace,
+		repl,
+		parallel,
+		progressive,
+		conf,
+	})
+}
 
-	fn compare(input: &str, expected: &str) {
-		let mut res = vec![];
-		process(&mut Cursor::new(&mut res), &mut Cursor::new(input)).unwrap();
+/// Parse the comma-separated list following option `opt`
+fn parse_list<T: std::str::FromStr, C: FromIterator<T>>(
+	opt: &str,
+	val: Option<String>,
+) -> Result<C, String> {
+	let val: String = parse_value(opt, val)?;
+	val.split(',')
+		.map(|v| parse_value(opt, Some(v.trim().to_owned())))
+		.collect()
+}
 
-		fn sort(csv: &str) -> String {
-			let i = csv.find('\n').unwrap();
-			let mut lines = csv[i + 1..].lines().collect::<Vec<_>>();
-			lines.sort();
-			lines.iter().fold(csv[..i].to_owned(), |mut w, line| {
-				w.push('\n');
-				w += line;
-				w
-			})
-		}
+/// Parse the `<min>[-<max>]` inclusive range following option `opt`
+fn parse_range(
+	opt: &str,
+	val: Option<String>,
+) -> Result<RangeInclusive<u32>, String> {
+	let val: String = parse_value(opt, val)?;
+	let (min, max) = val.split_once('-').unwrap_or((&val, &val));
+	let min = parse_value(opt, Some(min.to_owned()))?;
+	let max = parse_value(opt, Some(max.to_owned()))?;
+	Ok(min..=max)
+}
 
-		assert_eq!(sort(expected), sort(&String::from_utf8(res).unwrap()));
-	}
+/// Parse the value following option `opt`
+fn parse_value<T: std::str::FromStr>(
+	opt: &str,
+	val: Option<String>,
+) -> Result<T, String> {
+	val.and_then(|v| v.parse().ok())
+		.ok_or_else(|| format!("invalid or missing value for {}", opt))
 }
@@ -0,0 +1,244 @@
+//! Helpers for generating test and benchmark inputs and comparing outputs
+
+use std::{
+	collections::{BTreeMap, BTreeSet},
+	fmt::Write,
+};
+
+/// Generate a realistic input CSV of `n_rows` transactions spread over
+/// `n_clients` clients.
+///
+/// Most rows are deposits and withdrawals. A fraction of them disputes
+/// existing deposits and then resolves or charges back the disputed ones.
+/// The same `seed` always produces the same input.
+pub fn generate_input(seed: u64, n_clients: u16, n_rows: usize) -> String {
+	let mut rng = SplitMix64(seed);
+	let n_clients = n_clients.max(1) as usize;
+
+	// Undisputed deposits and ongoing disputes per client for generating
+	// valid references
+	let mut deposits = vec![Vec::<u32>::new(); n_clients];
+	let mut disputed = vec![Vec::<u32>::new(); n_clients];
+
+	let mut out = String::with_capacity(n_rows.saturating_mul(24));
+	out += "type, client, tx, amount\n";
+	let mut tx = 0;
+	for _ in 0..n_rows {
+		let i = rng.below(n_clients as u64) as usize;
+		let client = i + 1;
+		let roll = rng.below(100);
+
+		if roll < 5 && !disputed[i].is_empty() {
+			let j = rng.below(disputed[i].len() as u64) as usize;
+			let tx = disputed[i].swap_remove(j);
+			if roll < 4 {
+				writeln!(out, "resolve, {}, {},", client, tx).unwrap();
+				deposits[i].push(tx);
+			} else {
+				writeln!(out, "chargeback, {}, {},", client, tx).unwrap();
+			}
+		} else if roll < 10 && !deposits[i].is_empty() {
+			let j = rng.below(deposits[i].len() as u64) as usize;
+			let tx = deposits[i].swap_remove(j);
+			writeln!(out, "dispute, {}, {},", client, tx).unwrap();
+			disputed[i].push(tx);
+		} else {
+			tx += 1;
+			let typ = if roll < 70 { "deposit" } else { "withdrawal" };
+
+			// Up to 1000 major units with 4 decimal places
+			let amount = rng.below(1_000 * 10_000) + 1;
+			writeln!(
+				out,
+				"{}, {}, {}, {}.{:04}",
+				typ,
+				client,
+				tx,
+				amount / 10_000,
+				amount % 10_000
+			)
+			.unwrap();
+			if typ == "deposit" {
+				deposits[i].push(tx);
+			}
+		}
+	}
+	out
+}
+
+/// Differing fields of one client's account between two outputs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountDiff {
+	/// Client ID
+	pub client: u16,
+
+	/// Differing fields in column name order
+	pub fields: Vec<FieldDiff>,
+}
+
+/// Differing field of an account between two outputs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldDiff {
+	/// Column name
+	pub name: String,
+
+	/// Value in the first output. `None`, if the column or account is missing.
+	pub a: Option<String>,
+
+	/// Value in the second output. `None`, if the column or account is
+	/// missing.
+	pub b: Option<String>,
+}
+
+/// Compare output CSVs `a` and `b` by client regardless of row order and
+/// leading whitespace.
+///
+/// Rows without a numeric client ID, like the checksum row, are ignored.
+/// Panics, if either output is not valid CSV.
+pub fn diff_outputs(a: &str, b: &str) -> Vec<AccountDiff> {
+	let (a, b) = (parse_output(a), parse_output(b));
+	let none = BTreeMap::new();
+	a.keys()
+		.chain(b.keys())
+		.collect::<BTreeSet<_>>()
+		.into_iter()
+		.filter_map(|client| {
+			let fa = a.get(client).unwrap_or(&none);
+			let fb = b.get(client).unwrap_or(&none);
+			let fields = fa
+				.keys()
+				.chain(fb.keys())
+				.collect::<BTreeSet<_>>()
+				.into_iter()
+				.filter(|name| fa.get(*name) != fb.get(*name))
+				.map(|name| FieldDiff {
+					name: name.clone(),
+					a: fa.get(name).cloned(),
+					b: fb.get(name).cloned(),
+				})
+				.collect::<Vec<_>>();
+			(!fields.is_empty()).then_some(AccountDiff {
+				client: *client,
+				fields,
+			})
+		})
+		.collect()
+}
+
+/// Fields other than the client ID of each client's row of output CSV `csv`
+/// by column name
+fn parse_output(csv: &str) -> BTreeMap<u16, BTreeMap<String, String>> {
+	let mut r = csv::ReaderBuilder::new()
+		.trim(csv::Trim::All)
+		// Checksum row has fewer fields
+		.flexible(true)
+		.from_reader(csv.as_bytes());
+	let headers = r.headers().expect("invalid output CSV").clone();
+	let client_col = headers.iter().position(|h| h == "client");
+	r.records()
+		.map(|rec| rec.expect("invalid output CSV"))
+		.filter_map(|rec| {
+			let client = rec.get(client_col?)?.parse().ok()?;
+			let fields = headers
+				.iter()
+				.zip(&rec)
+				.filter(|(name, _)| *name != "client")
+				.map(|(name, v)| (name.to_owned(), v.to_owned()))
+				.collect();
+			Some((client, fields))
+		})
+		.collect()
+}
+
+/// Minimal seedable pseudo-random number generator.
+///
+/// Plenty for generating test data and avoids depending on the output of an
+/// external crate staying stable across versions.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+	fn next(&mut self) -> u64 {
+		self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+		let mut z = self.0;
+		z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+		z ^ (z >> 31)
+	}
+
+	/// Random number in `0..n`
+	fn below(&mut self, n: u64) -> u64 {
+		self.next() % n
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::{diff_outputs, generate_input, AccountDiff, FieldDiff};
+
+	// Same seed produces the same input
+	#[test]
+	fn deterministic() {
+		let input = generate_input(7, 10, 1000);
+		assert_eq!(input, generate_input(7, 10, 1000));
+		assert_ne!(input, generate_input(8, 10, 1000));
+		assert_eq!(input.lines().count(), 1001);
+
+		crate::process(
+			&mut std::io::sink(),
+			&mut input.as_bytes(),
+			&Default::default(),
+		)
+		.unwrap();
+	}
+
+	// Differing fields reported per client regardless of row order
+	#[test]
+	fn diff() {
+		let diff = diff_outputs(
+			"client,available,held,total,locked
+			1,1.0000,0.0000,1.0000,false
+			2,2.0000,0.0000,2.0000,false
+			3,3.0000,0.0000,3.0000,false",
+			"client,available,held,total,locked
+			3,3.0000,0.0000,3.0000,false
+			2,0.5000,1.5000,2.0000,true
+			4,4.0000,0.0000,4.0000,false",
+		);
+		let field = |name: &str, a: Option<&str>, b: Option<&str>| FieldDiff {
+			name: name.into(),
+			a: a.map(Into::into),
+			b: b.map(Into::into),
+		};
+		assert_eq!(
+			diff,
+			[
+				AccountDiff {
+					client: 1,
+					fields: vec![
+						field("available", Some("1.0000"), None),
+						field("held", Some("0.0000"), None),
+						field("locked", Some("false"), None),
+						field("total", Some("1.0000"), None),
+					],
+				},
+				AccountDiff {
+					client: 2,
+					fields: vec![
+						field("available", Some("2.0000"), Some("0.5000")),
+						field("held", Some("0.0000"), Some("1.5000")),
+						field("locked", Some("false"), Some("true")),
+					],
+				},
+				AccountDiff {
+					client: 4,
+					fields: vec![
+						field("available", None, Some("4.0000")),
+						field("held", None, Some("0.0000")),
+						field("locked", None, Some("false")),
+						field("total", None, Some("4.0000")),
+					],
+				},
+			]
+		);
+	}
+}